@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Default, Debug, Serialize, Deserialize)]
@@ -5,6 +8,20 @@ pub struct AtriOneBotConfig {
     #[serde(rename = "server")]
     pub servers: Vec<OneBotServer>,
     pub heartbeat: HeartbeatConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub send_queue: SendQueueConfig,
+    #[serde(default)]
+    pub message_cache: MessageCacheConfig,
+    #[serde(default)]
+    pub contact_cache: ContactCacheConfig,
+    #[serde(default)]
+    pub batch: BatchConfig,
+    #[serde(default)]
+    pub websocket: WebSocketConfig,
+    #[serde(default)]
+    pub timeout: TimeoutConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -22,13 +39,218 @@ pub enum OneBotServer {
         host: String,
         port: u16,
         access_token: Option<String>,
+        /// 请求未指定`self`时使用的机器人账号
+        default_bot: Option<i64>,
+        #[serde(default)]
+        protocol: OneBotProtocol,
+        #[serde(default)]
+        message_format: MessageFormat,
     },
     #[serde(rename = "ws-rev")]
     WebSocketReverse,
 }
 
+/// 服务端使用的OneBot协议版本
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OneBotProtocol {
+    V11,
+    #[default]
+    V12,
+}
+
+/// 事件与发送消息动作中消息的格式
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageFormat {
+    /// 消息段数组
+    #[default]
+    Array,
+    /// CQ码字符串
+    String,
+}
+
 #[derive(Default, Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct HeartbeatConfig {
     pub enabled: bool,
     pub interval: i64,
 }
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    pub mode: RateLimitMode,
+    /// 排队模式下的最长等待时间, 单位毫秒
+    pub max_wait: u64,
+    pub default: BucketConfig,
+    pub send_message: BucketConfig,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            mode: RateLimitMode::Reject,
+            max_wait: 5000,
+            default: BucketConfig {
+                capacity: 20,
+                per_second: 10.0,
+            },
+            send_message: BucketConfig {
+                capacity: 5,
+                per_second: 1.0,
+            },
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitMode {
+    #[default]
+    Reject,
+    Queue,
+}
+
+#[derive(Default, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct BucketConfig {
+    /// 令牌桶容量, 即允许的突发请求数
+    pub capacity: u32,
+    /// 每秒补充的令牌数, 不大于0时不限流
+    pub per_second: f64,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SendQueueConfig {
+    pub enabled: bool,
+    pub group: TargetQueueConfig,
+    pub friend: TargetQueueConfig,
+}
+
+impl Default for SendQueueConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            group: TargetQueueConfig {
+                min_interval: 1000,
+                jitter: 500,
+                max_length: 20,
+                timeout: 30000,
+            },
+            friend: TargetQueueConfig {
+                min_interval: 500,
+                jitter: 300,
+                max_length: 20,
+                timeout: 30000,
+            },
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct TargetQueueConfig {
+    /// 同一目标两条消息间的最小间隔, 单位毫秒
+    pub min_interval: u64,
+    /// 在最小间隔上附加的随机抖动上限, 单位毫秒
+    pub jitter: u64,
+    /// 队列最大长度, 为0时不限制
+    pub max_length: usize,
+    /// 排队超时时间, 单位毫秒, 为0时不限制
+    pub timeout: u64,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessageCacheConfig {
+    /// 每个机器人缓存的消息数, 为0时不缓存
+    pub capacity: usize,
+    /// 是否将缓存写入文件
+    pub persist: bool,
+    /// 定期写入文件的间隔, 单位秒, 为0时仅在插件关闭时写入
+    pub save_interval: u64,
+}
+
+impl Default for MessageCacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1000,
+            persist: false,
+            save_interval: 60,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContactCacheConfig {
+    pub enabled: bool,
+    /// 缓存有效期, 单位秒
+    pub ttl: u64,
+}
+
+impl Default for ContactCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ttl: 300,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BatchConfig {
+    /// 同时执行的动作数
+    pub concurrency: usize,
+    /// 单次批量请求的最大动作数
+    pub max_requests: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 8,
+            max_requests: 100,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebSocketConfig {
+    /// 单个连接同时处理的动作数, 超出时直接返回繁忙错误
+    pub max_in_flight: usize,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self { max_in_flight: 16 }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    /// 动作的默认超时时间, 单位毫秒, 为0时不限制
+    pub default: u64,
+    /// 按动作名设置的超时时间, 单位毫秒
+    pub actions: HashMap<String, u64>,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            default: 60000,
+            actions: HashMap::new(),
+        }
+    }
+}
+
+impl TimeoutConfig {
+    pub fn of(&self, action: &str) -> Option<Duration> {
+        let millis = self.actions.get(action).copied().unwrap_or(self.default);
+        (millis > 0).then(|| Duration::from_millis(millis))
+    }
+}
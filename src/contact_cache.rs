@@ -0,0 +1,168 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::ContactCacheConfig;
+use crate::data::contact::{GroupInfo, GroupMemberInfo, UserInfo};
+
+struct TtlMap<K, V> {
+    ttl: Duration,
+    entries: Mutex<HashMap<K, (Instant, V)>>,
+}
+
+impl<K: Eq + Hash, V: Clone> TtlMap<K, V> {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn get(&self, key: &K) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        match entries.get(key) {
+            Some((time, val)) if time.elapsed() < self.ttl => Some(val.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn with<R>(&self, key: &K, f: impl FnOnce(&V) -> R) -> Option<R> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries
+            .get(key)
+            .filter(|(time, _)| time.elapsed() < self.ttl)
+            .map(|(_, val)| f(val))
+    }
+
+    fn insert(&self, key: K, val: V) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.insert(key, (Instant::now(), val));
+    }
+
+    fn remove(&self, key: &K) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.remove(key);
+    }
+
+    fn retain(&self, mut f: impl FnMut(&K) -> bool) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.retain(|k, _| f(k));
+    }
+}
+
+/// 按机器人缓存好友, 群与群员列表
+///
+/// Atri在本地保存好友与群列表, 缓存这两者收效甚微, 主要用于减少获取群员列表的请求
+pub struct ContactCache {
+    enabled: bool,
+    friends: TtlMap<i64, Vec<UserInfo>>,
+    groups: TtlMap<i64, Vec<GroupInfo>>,
+    members: TtlMap<(i64, i64), Vec<GroupMemberInfo>>,
+    /// 上次收到登录事件时在线的机器人
+    online: Mutex<HashSet<i64>>,
+}
+
+impl ContactCache {
+    pub fn new(config: ContactCacheConfig) -> Self {
+        let ttl = Duration::from_secs(config.ttl);
+
+        Self {
+            enabled: config.enabled && config.ttl > 0,
+            friends: TtlMap::new(ttl),
+            groups: TtlMap::new(ttl),
+            members: TtlMap::new(ttl),
+            online: Mutex::default(),
+        }
+    }
+
+    pub fn friends(&self, bot_id: i64) -> Option<Vec<UserInfo>> {
+        self.enabled.then(|| self.friends.get(&bot_id)).flatten()
+    }
+
+    pub fn set_friends(&self, bot_id: i64, friends: Vec<UserInfo>) {
+        if self.enabled {
+            self.friends.insert(bot_id, friends);
+        }
+    }
+
+    pub fn groups(&self, bot_id: i64) -> Option<Vec<GroupInfo>> {
+        self.enabled.then(|| self.groups.get(&bot_id)).flatten()
+    }
+
+    pub fn set_groups(&self, bot_id: i64, groups: Vec<GroupInfo>) {
+        if self.enabled {
+            self.groups.insert(bot_id, groups);
+        }
+    }
+
+    pub fn members(&self, bot_id: i64, group_id: i64) -> Option<Vec<GroupMemberInfo>> {
+        self.enabled
+            .then(|| self.members.get(&(bot_id, group_id)))
+            .flatten()
+    }
+
+    pub fn member(&self, bot_id: i64, group_id: i64, user_id: i64) -> Option<GroupMemberInfo> {
+        if !self.enabled {
+            return None;
+        }
+
+        let user_id = user_id.to_string();
+        self.members
+            .with(&(bot_id, group_id), |members| {
+                members.iter().find(|m| m.user_id == user_id).cloned()
+            })
+            .flatten()
+    }
+
+    pub fn set_members(&self, bot_id: i64, group_id: i64, members: Vec<GroupMemberInfo>) {
+        if self.enabled {
+            self.members.insert((bot_id, group_id), members);
+        }
+    }
+
+    /// 收到群消息时调用, 发送者不在缓存的群员列表中则说明成员有变动
+    ///
+    /// 群资料不含群员信息, 故群列表的缓存不受影响
+    pub fn on_group_message(&self, bot_id: i64, group_id: i64, sender: i64) {
+        let sender = sender.to_string();
+        let known = self.members.with(&(bot_id, group_id), |members| {
+            members.iter().any(|m| m.user_id == sender)
+        });
+
+        if known == Some(false) {
+            self.members.remove(&(bot_id, group_id));
+        }
+    }
+
+    /// 收到好友消息时调用, 发送者不在缓存的好友列表中则说明好友有变动
+    pub fn on_friend_message(&self, bot_id: i64, friend: i64) {
+        let friend = friend.to_string();
+        let known = self.friends.with(&bot_id, |friends| {
+            friends.iter().any(|f| f.user_id == friend)
+        });
+
+        if known == Some(false) {
+            self.friends.remove(&bot_id);
+        }
+    }
+
+    /// 收到登录事件时调用, `online`为当前在线的机器人
+    ///
+    /// Atri的登录事件不包含机器人账号, 因此与上次记录的在线机器人比较,
+    /// 仅清除新登录与已下线的机器人的缓存
+    pub fn on_bot_login(&self, online: impl IntoIterator<Item = i64>) {
+        let online: HashSet<i64> = online.into_iter().collect();
+        let mut known = self.online.lock().unwrap_or_else(|e| e.into_inner());
+        let changed: HashSet<i64> = known.symmetric_difference(&online).copied().collect();
+
+        self.friends.retain(|bot_id| !changed.contains(bot_id));
+        self.groups.retain(|bot_id| !changed.contains(bot_id));
+        self.members.retain(|(bot_id, _)| !changed.contains(bot_id));
+        *known = online;
+    }
+}
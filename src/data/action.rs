@@ -1,9 +1,11 @@
 use crate::data::contact::{GroupInfo, GroupMemberInfo, UserInfo};
 use crate::data::event::OneBotStatus;
 use crate::data::message::MessageElement;
+use crate::error::ActionError;
+use crate::extension;
+use crate::message_cache::CachedMessage;
 use atri_plugin::bot::Bot;
 use serde::{Deserialize, Serialize};
-use std::error::Error;
 
 #[derive(Debug, Serialize)]
 pub struct ActionResponse {
@@ -15,10 +17,11 @@ pub struct ActionResponse {
     pub echo: Option<String>,
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Serialize)]
 #[serde(untagged)]
 pub enum ActionData {
-    GetSupportActions(&'static [&'static str]),
+    GetSupportActions(Vec<&'static str>),
     GetStatus(OneBotStatus),
     GetVersion {
         #[serde(rename = "impl")]
@@ -30,6 +33,8 @@ pub enum ActionData {
         user_id: String,
         user_name: String,
         user_displayname: String,
+        #[serde(rename = "qq.avatar")]
+        avatar: String,
     },
     GetUserInfo(UserInfo),
     GetFriendList(Vec<UserInfo>),
@@ -37,11 +42,41 @@ pub enum ActionData {
     GetGroupList(Vec<GroupInfo>),
     GetGroupMemberInfo(GroupMemberInfo),
     GetGroupMemberList(Vec<GroupMemberInfo>),
+    /// 指定了分页或过滤参数时的群员列表
+    QQGetGroupMemberPage {
+        #[serde(rename = "qq.total")]
+        total: usize,
+        members: Vec<GroupMemberInfo>,
+    },
+    SendMessage {
+        message_id: String,
+        time: f64,
+        #[serde(rename = "qq.queue_position")]
+        queue_position: usize,
+        #[serde(rename = "qq.queue_wait")]
+        queue_wait: u64,
+    },
+    QQGetMessage(CachedMessage),
+    QQGetGroupHistory {
+        messages: Vec<CachedMessage>,
+        /// 仅包含插件运行期间缓存的消息, 为`true`时缓存中没有更早的消息,
+        /// 但群中可能仍有更早的消息
+        incomplete: bool,
+    },
+    QQBatch(Vec<ActionResponse>),
+    Extension(serde_json::Value),
 }
 
 impl ActionData {
-    pub fn support_actions() -> Self {
-        Self::GetSupportActions(&["get_supported_actions", "get_status", "get_version"])
+    /// 支持的动作列表, `cancellable`为`false`时不包含仅WebSocket连接支持的`qq.cancel`
+    pub fn support_actions(cancellable: bool) -> Self {
+        let mut actions = Action::SUPPORTED.to_vec();
+        if !cancellable {
+            actions.retain(|&name| name != "qq.cancel");
+        }
+        actions.extend(extension::names());
+
+        Self::GetSupportActions(actions)
     }
 
     pub fn version() -> Self {
@@ -54,10 +89,10 @@ impl ActionData {
 }
 
 impl ActionResponse {
-    pub fn from_err<E: Error>(err: E, code: i64, echo: Option<String>) -> Self {
+    pub fn from_err(err: ActionError, echo: Option<String>) -> Self {
         Self {
             status: ActionStatus::Failed,
-            retcode: code,
+            retcode: err.retcode(),
             data: None,
             message: err.to_string(),
             echo,
@@ -91,44 +126,137 @@ pub struct ActionRequest {
     pub bot_self: Option<BotData>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-#[serde(tag = "action", content = "params")]
-pub enum Action {
-    GetLatestEvents {
-        limit: i64,
-        timeout: i64,
-    },
-    GetSupportActions {},
-    GetSelfInfo {},
-    GetStatus {},
-    GetVersion {},
-    GetUserInfo {
-        user_id: String,
-    },
-    GetFriendList {},
-    GetGroupInfo {
-        group_id: String,
-    },
-    GetGroupList {},
-    GetGroupMemberInfo {
-        group_id: String,
-        user_id: String,
-    },
-    GetGroupMemberList {
-        group_id: String,
-    },
-    SetGroupName {
-        group_id: String,
-        group_name: String,
-    },
-    LeaveGroup {
-        group_id: String,
-    },
-    SendMessage(OneBotMessageAction),
-    DeleteMessage {
-        message_id: String,
-    },
+/// 声明动作及其名称
+///
+/// `handle_action`对`Action`进行穷尽匹配, `supported`中的动作均须实现,
+/// `get_supported_actions`的返回值也由此生成; `unsupported`中的动作仅能被解析,
+/// 处理时返回不支持的动作; 扩展动作见`extension`模块
+macro_rules! actions {
+    (
+        supported {
+            $($name:literal => $variant:ident $fields:tt),* $(,)?
+        }
+        unsupported {
+            $($u_name:literal => $u_variant:ident $u_fields:tt),* $(,)?
+        }
+    ) => {
+        #[derive(Debug, Serialize, Deserialize)]
+        #[serde(tag = "action", content = "params")]
+        pub enum Action {
+            $(
+            #[serde(rename = $name)]
+            $variant $fields,
+            )*
+            $(
+            #[serde(rename = $u_name)]
+            $u_variant $u_fields,
+            )*
+            /// 其他插件注册的扩展动作, 不经由serde解析
+            #[serde(skip)]
+            Extension {
+                name: extension::ExtensionName,
+                params: serde_json::Value,
+            },
+        }
+
+        impl Action {
+            pub const SUPPORTED: &'static [&'static str] = &[$($name),*];
+
+            pub fn exists(name: &str) -> bool {
+                matches!(name, $($name)|* $(| $u_name)*)
+            }
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Self::$variant { .. } => $name,)*
+                    $(Self::$u_variant { .. } => $u_name,)*
+                    Self::Extension { name, .. } => name.0,
+                }
+            }
+        }
+    };
+}
+
+actions! {
+    supported {
+        "get_supported_actions" => GetSupportedActions {},
+        "get_status" => GetStatus {},
+        "get_version" => GetVersion {},
+        "get_self_info" => GetSelfInfo {},
+        "get_user_info" => GetUserInfo {
+            user_id: String,
+        },
+        "get_friend_list" => GetFriendList {
+            #[serde(default)]
+            no_cache: bool,
+        },
+        "get_group_info" => GetGroupInfo {
+            group_id: String,
+        },
+        "get_group_list" => GetGroupList {
+            #[serde(default)]
+            no_cache: bool,
+        },
+        "get_group_member_info" => GetGroupMemberInfo {
+            group_id: String,
+            user_id: String,
+            #[serde(default)]
+            no_cache: bool,
+        },
+        "get_group_member_list" => GetGroupMemberList {
+            group_id: String,
+            #[serde(default)]
+            no_cache: bool,
+            #[serde(rename = "qq.offset")]
+            offset: Option<usize>,
+            #[serde(rename = "qq.limit")]
+            limit: Option<usize>,
+            /// 按昵称或群名片包含的文本过滤
+            #[serde(rename = "qq.name")]
+            name: Option<String>,
+        },
+        "set_group_name" => SetGroupName {
+            group_id: String,
+            group_name: String,
+        },
+        "leave_group" => LeaveGroup {
+            group_id: String,
+        },
+        "send_message" => SendMessage(OneBotMessageAction),
+        "qq.get_message" => QQGetMessage {
+            message_id: String,
+        },
+        "qq.get_group_history" => QQGetGroupHistory {
+            group_id: String,
+            /// 获取早于该消息的消息
+            message_id: Option<String>,
+            /// 获取早于该时间的消息
+            time: Option<f64>,
+            limit: Option<usize>,
+        },
+        "qq.set_group_member_card" => QQSetGroupMemberCard {
+            group_id: String,
+            user_id: String,
+            card: String,
+        },
+        // 取消当前WebSocket连接中以`echo`标识的动作,
+        // 同一连接中`echo`相同的动作不能同时执行
+        "qq.cancel" => QQCancel {
+            echo: String,
+        },
+        "qq.batch" => QQBatch {
+            requests: Vec<serde_json::Value>,
+        },
+    }
+    unsupported {
+        "get_latest_events" => GetLatestEvents {
+            limit: i64,
+            timeout: i64,
+        },
+        "delete_message" => DeleteMessage {
+            message_id: String,
+        },
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -138,6 +266,9 @@ pub enum OneBotMessageAction {
     Private {
         message: Vec<MessageElement>,
         user_id: String,
+        /// 临时会话所在的群, Atri提供临时会话前仅用于返回更准确的错误
+        #[serde(rename = "qq.group_id")]
+        group_id: Option<String>,
     },
     Group {
         message: Vec<MessageElement>,
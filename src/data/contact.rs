@@ -3,42 +3,80 @@ use atri_plugin::contact::group::Group;
 use atri_plugin::contact::member::NamedMember;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserInfo {
     pub user_id: String,
     pub user_name: String,
     #[serde(rename = "user_displayname")]
     pub user_display_name: String,
     pub user_remark: String,
+    #[serde(rename = "qq.avatar")]
+    pub avatar: String,
+    #[serde(rename = "qq.is_friend")]
+    pub is_friend: bool,
 }
 
 impl From<Friend> for UserInfo {
     fn from(f: Friend) -> Self {
+        // Atri暂未提供好友备注与资料, 备注按规范留空, 显示名称使用昵称
         Self {
             user_id: f.id().to_string(),
             user_name: f.nickname().into(),
             user_display_name: f.nickname().into(),
-            user_remark: f.nickname().into(),
+            user_remark: "".into(),
+            avatar: user_avatar(f.id()),
+            is_friend: true,
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl From<NamedMember> for UserInfo {
+    fn from(named: NamedMember) -> Self {
+        let display_name = if named.card_name().is_empty() {
+            named.nickname()
+        } else {
+            named.card_name()
+        };
+
+        Self {
+            user_id: named.id().to_string(),
+            user_name: named.nickname().into(),
+            user_display_name: display_name.into(),
+            user_remark: "".into(),
+            avatar: user_avatar(named.id()),
+            is_friend: false,
+        }
+    }
+}
+
+pub fn user_avatar(id: i64) -> String {
+    format!("https://q1.qlogo.cn/g?b=qq&nk={}&s=640", id)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GroupInfo {
     pub group_id: String,
     pub group_name: String,
+    #[serde(rename = "qq.avatar")]
+    pub avatar: String,
 }
 
 impl From<Group> for GroupInfo {
     fn from(g: Group) -> Self {
+        // Atri暂未提供群主, 群人数, 群容量与创建时间
         Self {
             group_id: g.id().to_string(),
             group_name: g.name().to_string(),
+            avatar: group_avatar(g.id()),
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+pub fn group_avatar(id: i64) -> String {
+    format!("https://p.qlogo.cn/gh/{0}/{0}/640", id)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GroupMemberInfo {
     pub user_id: String,
     pub user_name: String,
@@ -47,6 +85,7 @@ pub struct GroupMemberInfo {
 
 impl From<NamedMember> for GroupMemberInfo {
     fn from(named: NamedMember) -> Self {
+        // Atri暂未提供群员的身份, 头衔, 入群时间, 最后发言时间与禁言信息
         Self {
             user_id: named.id().to_string(),
             user_name: named.nickname().to_string(),
@@ -1,9 +1,11 @@
 use atri_plugin::message::at::At;
-use atri_plugin::message::meta::Reply;
+use atri_plugin::message::meta::{MessageMetadata, Reply};
 use atri_plugin::message::{MessageChain, MessageValue};
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
+use crate::error::ActionError;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "detail_type")]
@@ -17,6 +19,9 @@ pub enum OneBotMessageEvent {
         #[serde(flatten)]
         message: OneBotMessage,
         group_id: String,
+        /// 发送者, 兼容未记录发送者的旧消息缓存
+        #[serde(default)]
+        user_id: String,
     },
     Channel {
         #[serde(flatten)]
@@ -26,6 +31,16 @@ pub enum OneBotMessageEvent {
     },
 }
 
+impl OneBotMessageEvent {
+    pub fn message(&self) -> &OneBotMessage {
+        match self {
+            Self::Private { message, .. }
+            | Self::Group { message, .. }
+            | Self::Channel { message, .. } => message,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OneBotMessage {
     pub message_id: String,
@@ -33,16 +48,24 @@ pub struct OneBotMessage {
     pub alt_message: String,
 }
 
-impl From<MessageChain> for OneBotMessage {
-    fn from(chain: MessageChain) -> Self {
+/// 消息所在的会话
+#[derive(Debug, Clone, Copy)]
+pub enum Chat {
+    Group(i64),
+    Private(i64),
+}
+
+impl OneBotMessage {
+    /// 转换收到的消息, `chat`为消息所在的群或好友
+    pub fn from_chain(chain: MessageChain, chat: Chat) -> Self {
         let mut ob = Self {
-            message_id: "".to_string(),
+            message_id: Self::id_of(chat, chain.metadata()),
             message: vec![],
             alt_message: chain.to_string(),
         };
 
         if let Some(ref reply) = chain.metadata().reply {
-            ob.message.push(MessageElement::from(reply));
+            ob.message.push(MessageElement::reply(chat, reply));
         }
 
         let iter = chain.into_iter();
@@ -54,6 +77,23 @@ impl From<MessageChain> for OneBotMessage {
 
         ob
     }
+
+    /// 收到的消息的ID, 与回复元素中的消息ID格式一致; 没有消息序号时使用随机ID
+    pub fn id_of(chat: Chat, meta: &MessageMetadata) -> String {
+        match meta.seqs.first() {
+            Some(&seq) => message_id(chat, seq),
+            None => uuid::Uuid::new_v4().to_string(),
+        }
+    }
+}
+
+/// 由会话与消息序号组成消息ID, 消息序号仅在同一个群或好友中唯一;
+/// 群号与QQ号可能相同, 故ID中包含会话类型
+fn message_id(chat: Chat, seq: i32) -> String {
+    match chat {
+        Chat::Group(id) => format!("group_{}_{}", id, seq),
+        Chat::Private(id) => format!("private_{}_{}", id, seq),
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -94,6 +134,27 @@ pub enum MessageElement {
         message_id: String,
         user_id: String,
     },
+    /// 无法识别的消息元素, 仅标记此处有未能转换的内容
+    #[serde(rename = "qq.unknown")]
+    QQUnknown {},
+}
+
+impl MessageElement {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Text { .. } => "text",
+            Self::Image { .. } => "image",
+            Self::Mention { .. } => "mention",
+            Self::MentionAll {} => "mention_all",
+            Self::Voice { .. } => "voice",
+            Self::Audio { .. } => "audio",
+            Self::Video { .. } => "video",
+            Self::File { .. } => "file",
+            Self::Location { .. } => "location",
+            Self::Reply { .. } => "reply",
+            Self::QQUnknown {} => "qq.unknown",
+        }
+    }
 }
 
 impl From<MessageValue> for MessageElement {
@@ -107,37 +168,62 @@ impl From<MessageValue> for MessageElement {
                 user_id: at.target.to_string(),
             },
             MessageValue::AtAll => Self::MentionAll {},
-            _ => Self::Text { text: "".into() },
+            // Atri暂未区分表情, 闪照, 语音, 卡片与转发等消息, 均为不透明的Unknown
+            MessageValue::Unknown(_) => Self::QQUnknown {},
         }
     }
 }
 
-impl From<Reply> for MessageElement {
-    fn from(reply: Reply) -> Self {
-        Self::from(&reply)
-    }
-}
-
-impl From<&Reply> for MessageElement {
-    fn from(reply: &Reply) -> Self {
+impl MessageElement {
+    /// 转换回复元数据, `chat`为消息所在的群或好友
+    pub fn reply(chat: Chat, reply: &Reply) -> Self {
         Self::Reply {
-            message_id: reply.reply_seq.to_string(),
+            message_id: message_id(chat, reply.reply_seq),
             user_id: reply.sender.to_string(),
         }
     }
 }
 
-impl From<MessageElement> for MessageValue {
-    fn from(elem: MessageElement) -> Self {
-        match elem {
+impl TryFrom<MessageElement> for MessageValue {
+    type Error = ActionError;
+
+    fn try_from(elem: MessageElement) -> Result<Self, Self::Error> {
+        let val = match elem {
             MessageElement::Text { text } => Self::Text(text),
-            MessageElement::Image { file_id } => todo!(),
             MessageElement::Mention { user_id } => Self::At(At {
-                target: i64::from_str(&user_id).unwrap(),
+                target: i64::from_str(&user_id)
+                    .map_err(|e| ActionError::BadSegmentData(format!("{}: {}", user_id, e)))?,
                 display: "".into(),
             }),
             MessageElement::MentionAll {} => Self::AtAll,
-            _ => Self::Text("".into()),
-        }
+            // Atri暂未提供构造其余消息元素与设置回复元数据的接口, 不能静默丢弃
+            elem @ (MessageElement::Image { .. }
+            | MessageElement::Voice { .. }
+            | MessageElement::Audio { .. }
+            | MessageElement::Video { .. }
+            | MessageElement::File { .. }
+            | MessageElement::Location { .. }
+            | MessageElement::QQUnknown {}) => {
+                return Err(ActionError::UnsupportedSegment(elem.name().into()));
+            }
+            // 消息链构造器不能设置回复元数据, 在Atri提供接口前无法引用回复
+            MessageElement::Reply { .. } => {
+                return Err(ActionError::UnsupportedSegment("reply".into()));
+            }
+        };
+
+        Ok(val)
     }
 }
+
+pub fn to_message_chain(elems: Vec<MessageElement>) -> Result<MessageChain, ActionError> {
+    let mut builder = MessageChain::builder();
+    for elem in elems {
+        match MessageValue::try_from(elem)? {
+            MessageValue::Text(text) => builder.push_str(&text),
+            val => builder.push(val),
+        };
+    }
+
+    Ok(builder.build())
+}
@@ -0,0 +1,113 @@
+use std::fmt::{Display, Formatter};
+
+use atri_plugin::error::AtriError;
+
+/// 动作执行错误, 对应OneBot 12的返回码
+#[derive(Debug)]
+pub enum ActionError {
+    // 10xxx 请求错误
+    BadRequest(String),
+    UnsupportedAction(String),
+    BadParam(String),
+    UnsupportedParam(String),
+    UnsupportedSegment(String),
+    BadSegmentData(String),
+    WhoAmI,
+    UnknownSelf,
+
+    // 20xxx 处理器错误
+    InternalHandlerError(String),
+
+    /// 扩展动作返回的错误, 返回码由扩展动作指定
+    Extension(i64, String),
+
+    // 34xxx 平台错误
+    PlatformError(AtriError),
+
+    // 35xxx 执行逻辑错误
+    GroupNotFound,
+    FriendNotFound,
+    MemberNotFound,
+    UserNotFound,
+    MessageNotFound,
+    RequestNotFound,
+    SetGroupNameFailed(AtriError),
+    SetMemberCardFailed(AtriError),
+    LeaveGroupFailed,
+
+    // 36xxx 繁忙
+    RateLimited(&'static str),
+    SendQueueFull(usize),
+    SendQueueTimeout(usize),
+    Timeout(String),
+    Cancelled,
+    TooManyInFlight(usize),
+}
+
+impl ActionError {
+    pub fn retcode(&self) -> i64 {
+        match self {
+            Self::BadRequest(_) => 10001,
+            Self::UnsupportedAction(_) => 10002,
+            Self::BadParam(_) => 10003,
+            Self::UnsupportedParam(_) => 10004,
+            Self::UnsupportedSegment(_) => 10005,
+            Self::BadSegmentData(_) => 10006,
+            Self::WhoAmI => 10101,
+            Self::UnknownSelf => 10102,
+            Self::InternalHandlerError(_) => 20002,
+            Self::Extension(retcode, _) => *retcode,
+            Self::PlatformError(_) => 34001,
+            Self::GroupNotFound => 35002,
+            Self::FriendNotFound => 35003,
+            Self::MemberNotFound => 35004,
+            Self::UserNotFound => 35005,
+            Self::MessageNotFound => 35006,
+            Self::RequestNotFound => 35007,
+            Self::SetGroupNameFailed(_) => 35012,
+            Self::SetMemberCardFailed(_) => 35013,
+            Self::LeaveGroupFailed => 35021,
+            Self::RateLimited(_) => 36000,
+            Self::SendQueueFull(_) => 36001,
+            Self::SendQueueTimeout(_) => 36002,
+            Self::Timeout(_) => 36003,
+            Self::Cancelled => 36004,
+            Self::TooManyInFlight(_) => 36005,
+        }
+    }
+}
+
+impl Display for ActionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BadRequest(s) => write!(f, "无效的动作请求: {}", s),
+            Self::UnsupportedAction(s) => write!(f, "不支持的动作: {}", s),
+            Self::BadParam(s) => write!(f, "无效的动作参数: {}", s),
+            Self::UnsupportedParam(s) => write!(f, "不支持的动作参数: {}", s),
+            Self::UnsupportedSegment(s) => write!(f, "不支持的消息段: {}", s),
+            Self::BadSegmentData(s) => write!(f, "无效的消息段参数: {}", s),
+            Self::WhoAmI => f.write_str("未指定机器人账号, 且有多个机器人在线"),
+            Self::UnknownSelf => f.write_str("机器人不存在或未登陆"),
+            Self::InternalHandlerError(s) => write!(f, "动作处理器内部错误: {}", s),
+            Self::Extension(_, s) => f.write_str(s),
+            Self::PlatformError(e) => write!(f, "平台错误: {}", e),
+            Self::GroupNotFound => f.write_str("群不存在"),
+            Self::FriendNotFound => f.write_str("好友不存在"),
+            Self::MemberNotFound => f.write_str("群员不存在"),
+            Self::UserNotFound => f.write_str("用户不存在"),
+            Self::MessageNotFound => f.write_str("消息不存在或已过期"),
+            Self::RequestNotFound => f.write_str("动作请求不存在或已完成"),
+            Self::SetGroupNameFailed(e) => write!(f, "修改群名失败: {}", e),
+            Self::SetMemberCardFailed(e) => write!(f, "修改群名片失败: {}", e),
+            Self::LeaveGroupFailed => f.write_str("未退出群, 可能是已经退出"),
+            Self::RateLimited(action) => write!(f, "动作请求过于频繁: {}", action),
+            Self::SendQueueFull(len) => write!(f, "发送队列已满, 队列长度: {}", len),
+            Self::SendQueueTimeout(pos) => write!(f, "发送队列等待超时, 队列位置: {}", pos),
+            Self::Timeout(action) => write!(f, "动作执行超时: {}", action),
+            Self::Cancelled => f.write_str("动作已被取消"),
+            Self::TooManyInFlight(max) => write!(f, "正在处理的动作过多, 上限: {}", max),
+        }
+    }
+}
+
+impl std::error::Error for ActionError {}
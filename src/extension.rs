@@ -0,0 +1,187 @@
+//! 供同一Atri宿主中的其他插件注册扩展动作
+//!
+//! 本插件为cdylib, 其他插件需通过动态库符号调用`atri_onebot_register_action`.
+//! 扩展动作名须为`前缀.动作名`, 前缀`qq`保留给本插件.
+//! 处理函数接收动作参数的JSON, 返回形如`{"retcode": 0, "data": ..., "message": ""}`的JSON,
+//! 其中`retcode`与`message`可省略; 返回的字符串由注册时提供的释放函数释放
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::sync::{Arc, Mutex, RwLock};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::data::action::Action;
+use crate::error::ActionError;
+
+pub type ExtensionHandler =
+    extern "C" fn(params: *const c_char, user_data: *mut c_void) -> *mut c_char;
+pub type ExtensionFree = extern "C" fn(rsp: *mut c_char);
+
+/// 已注册的扩展动作名
+#[derive(Debug, Clone, Copy)]
+pub struct ExtensionName(pub &'static str);
+
+struct Extension {
+    name: ExtensionName,
+    handler: ExtensionHandler,
+    free: ExtensionFree,
+    user_data: *mut c_void,
+    /// 调用期间持有读锁, 注销时持有写锁置为`false`, 以等待进行中的调用结束
+    registered: RwLock<bool>,
+}
+
+// 注册方需保证处理函数与`user_data`可在任意线程使用
+unsafe impl Send for Extension {}
+unsafe impl Sync for Extension {}
+
+static EXTENSIONS: Mutex<BTreeMap<String, Arc<Extension>>> = Mutex::new(BTreeMap::new());
+
+/// 泄漏过的动作名, 重复注册同名动作时复用
+static LEAKED_NAMES: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+
+#[derive(Deserialize)]
+struct ExtensionResponse {
+    #[serde(default)]
+    retcode: i64,
+    #[serde(default)]
+    data: Value,
+    #[serde(default)]
+    message: String,
+}
+
+/// 注册成功返回0, 动作名无效返回-1, 动作已存在返回-2
+///
+/// # Safety
+///
+/// `name`须为有效的C字符串, `handler`与`free`在`atri_onebot_unregister_action`返回前须保持可用
+#[no_mangle]
+pub unsafe extern "C" fn atri_onebot_register_action(
+    name: *const c_char,
+    handler: ExtensionHandler,
+    free: ExtensionFree,
+    user_data: *mut c_void,
+) -> i32 {
+    let name = match unsafe { CStr::from_ptr(name) }.to_str() {
+        Ok(name) => name,
+        Err(_) => return -1,
+    };
+
+    let valid = match name.split_once('.') {
+        Some((prefix, action)) => !prefix.is_empty() && prefix != "qq" && !action.is_empty(),
+        None => false,
+    };
+    if !valid {
+        return -1;
+    }
+
+    let mut extensions = EXTENSIONS.lock().unwrap_or_else(|e| e.into_inner());
+    if Action::exists(name) || extensions.contains_key(name) {
+        return -2;
+    }
+
+    // 动作名用于限流与超时设置, 故不回收, 同名动作重复注册时复用
+    let mut leaked_names = LEAKED_NAMES.lock().unwrap_or_else(|e| e.into_inner());
+    let leaked = match leaked_names.get(name) {
+        Some(&leaked) => leaked,
+        None => {
+            let leaked: &'static str = Box::leak(name.to_owned().into_boxed_str());
+            leaked_names.insert(leaked);
+            leaked
+        }
+    };
+    extensions.insert(
+        name.to_owned(),
+        Arc::new(Extension {
+            name: ExtensionName(leaked),
+            handler,
+            free,
+            user_data,
+            registered: RwLock::new(true),
+        }),
+    );
+
+    0
+}
+
+/// 注销成功返回0, 动作不存在返回-1
+///
+/// 阻塞至该动作进行中的调用全部结束后返回, 此后不会再调用`handler`与`free`
+///
+/// # Safety
+///
+/// `name`须为有效的C字符串; 不可在该动作的`handler`中调用, 否则将永久阻塞
+#[no_mangle]
+pub unsafe extern "C" fn atri_onebot_unregister_action(name: *const c_char) -> i32 {
+    let name = unsafe { CStr::from_ptr(name) }.to_string_lossy();
+
+    let ext = {
+        let mut extensions = EXTENSIONS.lock().unwrap_or_else(|e| e.into_inner());
+        match extensions.remove(&*name) {
+            Some(ext) => ext,
+            None => return -1,
+        }
+    };
+
+    *ext.registered.write().unwrap_or_else(|e| e.into_inner()) = false;
+    0
+}
+
+/// 查找已注册的扩展动作
+pub fn find(name: &str) -> Option<ExtensionName> {
+    let extensions = EXTENSIONS.lock().unwrap_or_else(|e| e.into_inner());
+    extensions.get(name).map(|ext| ext.name)
+}
+
+pub fn names() -> Vec<&'static str> {
+    let extensions = EXTENSIONS.lock().unwrap_or_else(|e| e.into_inner());
+    extensions.values().map(|ext| ext.name.0).collect()
+}
+
+/// 在阻塞线程中调用扩展动作的处理函数
+pub async fn call(ExtensionName(name): ExtensionName, params: Value) -> Result<Value, ActionError> {
+    let ext = {
+        let extensions = EXTENSIONS.lock().unwrap_or_else(|e| e.into_inner());
+        extensions
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ActionError::UnsupportedAction(name.into()))?
+    };
+
+    let params =
+        CString::new(params.to_string()).map_err(|e| ActionError::BadParam(e.to_string()))?;
+
+    let rsp = tokio::task::spawn_blocking(move || {
+        // 取得扩展后可能已被注销, 持有读锁期间注销方会等待调用结束
+        let registered = ext.registered.read().unwrap_or_else(|e| e.into_inner());
+        if !*registered {
+            return Err(ActionError::UnsupportedAction(name.into()));
+        }
+
+        let ptr = (ext.handler)(params.as_ptr(), ext.user_data);
+        if ptr.is_null() {
+            return Err(ActionError::InternalHandlerError(format!(
+                "扩展动作无返回值: {}",
+                name
+            )));
+        }
+
+        let rsp = unsafe { CStr::from_ptr(ptr) }
+            .to_string_lossy()
+            .into_owned();
+        (ext.free)(ptr);
+        Ok(rsp)
+    })
+    .await
+    .map_err(|e| ActionError::InternalHandlerError(e.to_string()))??;
+
+    let rsp: ExtensionResponse = serde_json::from_str(&rsp)
+        .map_err(|e| ActionError::InternalHandlerError(format!("{}: {}", name, e)))?;
+
+    if rsp.retcode != 0 {
+        return Err(ActionError::Extension(rsp.retcode, rsp.message));
+    }
+
+    Ok(rsp.data)
+}
@@ -1,60 +1,189 @@
+use std::collections::HashMap;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
 use atri_plugin::bot::Bot;
+use atri_plugin::contact::friend::Friend;
+use atri_plugin::contact::group::Group;
+use tokio::sync::{oneshot, Semaphore};
+use tokio::task::JoinHandle;
 
+use crate::config::{BatchConfig, MessageFormat, TimeoutConfig};
+use crate::contact_cache::ContactCache;
 use crate::data::action::{
-    Action, ActionData, ActionRequest, ActionResponse, ActionStatus, OneBotMessageAction,
+    Action, ActionData, ActionRequest, ActionResponse, BotData, OneBotMessageAction,
 };
-use crate::data::contact::{GroupInfo, GroupMemberInfo, UserInfo};
+use crate::data::contact::{user_avatar, GroupInfo, GroupMemberInfo, UserInfo};
 use crate::data::event::{BotStatus, OneBotStatus};
+use crate::data::message::{to_message_chain, MessageElement, OneBotMessage, OneBotMessageEvent};
+use crate::error::ActionError;
+use crate::extension;
+use crate::http::BotQuery;
+use crate::limiter::{LimitKey, RateLimiter};
+use crate::message_cache::{CachedMessage, MessageCache};
+use crate::scheduler::{ScheduleError, SendScheduler, SendTarget};
+use crate::v11::cq;
+use crate::websocket::sys_time;
 
-macro_rules! id_parse {
-    ($id:expr, $echo:ident) => {
-        match i64::from_str($id) {
-            Ok(id) => id,
-            Err(e) => {
-                return ActionResponse {
-                    status: ActionStatus::Failed,
-                    retcode: 10003,
-                    data: None,
-                    message: e.to_string(),
-                    echo: $echo,
-                }
+/// 单个服务端的动作处理上下文
+#[derive(Clone)]
+pub struct ActionContext {
+    pub access_token: Option<String>,
+    /// 请求未指定`self`时使用的机器人账号
+    pub default_bot: Option<i64>,
+    pub limiter: Arc<RateLimiter>,
+    pub scheduler: Arc<SendScheduler>,
+    pub messages: Arc<MessageCache>,
+    pub contacts: Arc<ContactCache>,
+    pub message_format: MessageFormat,
+    pub batch: BatchConfig,
+    pub timeout: Arc<TimeoutConfig>,
+    /// WebSocket连接中可取消的动作, HTTP请求之间互不关联, 为`None`时不支持取消
+    pub in_flight: Option<Arc<InFlightActions>>,
+}
+
+impl ActionContext {
+    /// 为单个连接或请求创建上下文, 连接参数中的`bot_id`与`message_format`优先于服务端配置
+    pub fn with_query(&self, query: &str) -> Self {
+        let mut ctx = self.clone();
+        if let Ok(BotQuery {
+            bot_id,
+            message_format,
+        }) = serde_urlencoded::from_str(query)
+        {
+            if let Some(bot_id) = bot_id {
+                ctx.default_bot = Some(bot_id);
+            }
+            if let Some(format) = message_format {
+                ctx.message_format = format;
             }
         }
-    };
+
+        ctx
+    }
 }
 
-macro_rules! get_group {
-    ($bot:expr, $id:expr, $echo:ident) => {
-        if let Some(g) = $bot.find_group($id) {
-            g
-        } else {
-            return ActionResponse {
-                status: ActionStatus::Failed,
-                retcode: 35002,
-                data: None,
-                message: "群不存在".into(),
-                echo: $echo,
-            };
+/// 按`echo`记录正在执行的动作, 用于`qq.cancel`
+#[derive(Default)]
+pub struct InFlightActions {
+    tasks: Mutex<HashMap<String, InFlightTask>>,
+    next_id: AtomicU64,
+}
+
+struct InFlightTask {
+    id: u64,
+    /// 预留`echo`后才创建任务, 此前为`None`
+    task: Option<JoinHandle<()>>,
+}
+
+impl InFlightActions {
+    /// 预留`echo`, 已有相同`echo`的动作在执行时返回`None`
+    fn reserve(&self, echo: &str) -> Option<u64> {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        if tasks.contains_key(echo) {
+            return None;
         }
-    };
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        tasks.insert(echo.to_owned(), InFlightTask { id, task: None });
+        Some(id)
+    }
+
+    /// 记录预留`echo`后创建的任务, 预留期间已被取消时立即中止任务
+    fn start(&self, echo: &str, id: u64, task: JoinHandle<()>) {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        match tasks.get_mut(echo) {
+            Some(entry) if entry.id == id => entry.task = Some(task),
+            _ => task.abort(),
+        }
+    }
+
+    /// 移除记录, 仅当记录的仍是`id`对应的动作时移除
+    fn remove(&self, echo: &str, id: u64) {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        if tasks.get(echo).is_some_and(|entry| entry.id == id) {
+            tasks.remove(echo);
+        }
+    }
+
+    fn cancel(&self, echo: &str) -> bool {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        match tasks.remove(echo) {
+            Some(entry) => {
+                if let Some(task) = entry.task {
+                    task.abort();
+                }
+                true
+            }
+            None => false,
+        }
+    }
 }
 
-macro_rules! get_friend {
-    ($bot:expr, $id:expr, $echo:ident) => {
-        if let Some(g) = $bot.find_friend($id) {
-            g
-        } else {
-            return ActionResponse {
-                status: ActionStatus::Failed,
-                retcode: 35003,
-                data: None,
-                message: "好友不存在".into(),
-                echo: $echo,
-            };
+/// 解析动作请求
+///
+/// 解析失败时, 若请求中带有`echo`则一并返回;
+/// 消息格式为CQ码字符串时, `send_message`的字符串消息按CQ码解析
+pub fn parse_request(
+    json: &str,
+    format: MessageFormat,
+) -> Result<ActionRequest, (ActionError, Option<String>)> {
+    let value = serde_json::from_str::<serde_json::Value>(json)
+        .map_err(|e| (ActionError::BadRequest(e.to_string()), None))?;
+
+    parse_value(value, format)
+}
+
+fn parse_value(
+    mut value: serde_json::Value,
+    format: MessageFormat,
+) -> Result<ActionRequest, (ActionError, Option<String>)> {
+    let echo = value
+        .get("echo")
+        .and_then(|echo| echo.as_str())
+        .map(String::from);
+
+    let action = match value.get("action").and_then(|action| action.as_str()) {
+        Some(action) => action,
+        None => {
+            return Err((ActionError::BadRequest("缺少动作名称".into()), echo));
         }
     };
+
+    if !Action::exists(action) {
+        let name = match extension::find(action) {
+            Some(name) => name,
+            None => return Err((ActionError::UnsupportedAction(action.into()), echo)),
+        };
+
+        let bot_self = match value.get("self") {
+            Some(bot_self) => serde_json::from_value(bot_self.clone())
+                .map_err(|e| (ActionError::BadParam(e.to_string()), echo.clone()))?,
+            None => None,
+        };
+        let params = value
+            .get_mut("params")
+            .map(serde_json::Value::take)
+            .unwrap_or_default();
+
+        return Ok(ActionRequest {
+            action: Action::Extension { name, params },
+            echo,
+            bot_self,
+        });
+    }
+
+    if format == MessageFormat::String && action == "send_message" {
+        if let Some(message) = value.pointer_mut("/params/message") {
+            if let Some(cq) = message.as_str() {
+                let elems = cq::to_elements(cq).map_err(|e| (e, echo.clone()))?;
+                *message = serde_json::to_value(elems).unwrap_or_default();
+            }
+        }
+    }
+
+    serde_json::from_value(value).map_err(|e| (ActionError::BadParam(e.to_string()), echo))
 }
 
 pub async fn handle_action(
@@ -63,192 +192,561 @@ pub async fn handle_action(
         echo,
         bot_self,
     }: ActionRequest,
+    ctx: &ActionContext,
 ) -> ActionResponse {
-    match &action {
-        Action::GetStatus {} => {
-            return ActionResponse::from_data(
-                Some(ActionData::GetStatus(OneBotStatus {
-                    good: true,
-                    bots: Bot::list().into_iter().map(BotStatus::from).collect(),
-                })),
-                echo,
+    let name = action.name();
+    let result = match ctx.timeout.of(name) {
+        Some(timeout) => tokio::time::timeout(timeout, handle(action, bot_self, ctx))
+            .await
+            .unwrap_or_else(|_| Err(ActionError::Timeout(name.into()))),
+        None => handle(action, bot_self, ctx).await,
+    };
+
+    match result {
+        Ok(data) => ActionResponse::from_data(data, echo),
+        Err(e) => ActionResponse::from_err(e, echo),
+    }
+}
+
+/// 处理可被`qq.cancel`取消的动作, 仅带有`echo`的请求可被取消
+pub async fn handle_cancellable(req: ActionRequest, ctx: &ActionContext) -> ActionResponse {
+    let (echo, in_flight) = match (req.echo.clone(), &ctx.in_flight) {
+        (Some(echo), Some(in_flight)) => (echo, Arc::clone(in_flight)),
+        _ => return handle_action(req, ctx).await,
+    };
+
+    let id = match in_flight.reserve(&echo) {
+        Some(id) => id,
+        None => {
+            return ActionResponse::from_err(
+                ActionError::BadRequest(format!("已有相同echo的动作正在执行: {}", echo)),
+                Some(echo),
             );
         }
-        Action::GetSupportActions {} => {
-            return ActionResponse::from_data(Some(ActionData::support_actions()), echo);
-        }
-        Action::GetVersion {} => {
-            return ActionResponse::from_data(Some(ActionData::version()), echo);
+    };
+
+    let (tx, rx) = oneshot::channel();
+    let task = {
+        let ctx = ctx.clone();
+        tokio::task::spawn_local(async move {
+            let _ = tx.send(handle_action(req, &ctx).await);
+        })
+    };
+    in_flight.start(&echo, id, task);
+
+    let rsp = rx.await;
+    in_flight.remove(&echo, id);
+
+    rsp.unwrap_or_else(|_| ActionResponse::from_err(ActionError::Cancelled, Some(echo)))
+}
+
+/// 并发执行批量请求, 按请求顺序返回响应
+pub async fn handle_batch(
+    requests: Vec<serde_json::Value>,
+    ctx: &ActionContext,
+) -> Result<Vec<ActionResponse>, ActionError> {
+    if requests.len() > ctx.batch.max_requests {
+        return Err(ActionError::BadRequest(format!(
+            "批量请求的动作数超出上限: {}",
+            ctx.batch.max_requests
+        )));
+    }
+
+    let semaphore = Arc::new(Semaphore::new(ctx.batch.concurrency.max(1)));
+    let mut handles = AbortOnDrop(
+        requests
+            .into_iter()
+            .map(|value| {
+                let ctx = ctx.clone();
+                let semaphore = Arc::clone(&semaphore);
+                tokio::task::spawn_local(async move {
+                    let req = match parse_value(value, ctx.message_format) {
+                        Ok(req) => req,
+                        Err((e, echo)) => return ActionResponse::from_err(e, echo),
+                    };
+
+                    if let Action::QQBatch { .. } = req.action {
+                        return ActionResponse::from_err(
+                            ActionError::BadRequest("不支持嵌套的批量请求".into()),
+                            req.echo,
+                        );
+                    }
+
+                    let _permit = semaphore.acquire_owned().await;
+                    handle_action(req, &ctx).await
+                })
+            })
+            .collect(),
+    );
+
+    let mut responses = Vec::with_capacity(handles.0.len());
+    for handle in &mut handles.0 {
+        let rsp = handle
+            .await
+            .map_err(|e| ActionError::InternalHandlerError(e.to_string()))?;
+        responses.push(rsp);
+    }
+
+    Ok(responses)
+}
+
+/// 批量请求超时或被取消时, 中止尚未完成的子动作
+struct AbortOnDrop<T>(Vec<JoinHandle<T>>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        for handle in &self.0 {
+            handle.abort();
         }
-        _ => {}
     }
+}
 
-    let bot_id = if let Some(b) = bot_self {
-        id_parse!(&b.user_id, echo)
-    } else {
-        return ActionResponse {
-            status: ActionStatus::Failed,
-            retcode: 10101,
-            data: None,
-            message: "未指定机器人账号".into(),
-            echo,
-        };
+/// 执行动作, 供其他协议的动作复用
+pub async fn handle(
+    action: Action,
+    bot_self: Option<BotData>,
+    ctx: &ActionContext,
+) -> Result<Option<ActionData>, ActionError> {
+    let key = LimitKey {
+        access_token: ctx.access_token.clone(),
+        bot_id: limit_bot_id(&action, &bot_self, ctx)?,
+        action: action.name(),
     };
 
-    let bot = if let Some(bot) = Bot::find(bot_id) {
-        bot
-    } else {
-        return ActionResponse {
-            status: ActionStatus::Failed,
-            retcode: 35001,
-            data: None,
-            message: "机器人不存在或未登陆".into(),
-            echo,
-        };
-    };
+    let wait = ctx
+        .limiter
+        .acquire(key)
+        .map_err(|()| ActionError::RateLimited(action.name()))?;
+    if !wait.is_zero() {
+        tokio::time::sleep(wait).await;
+    }
 
     let data = match action {
-        Action::GetSelfInfo {} => Some(ActionData::GetSelfInfo {
-            user_id: bot_id.to_string(),
-            user_name: bot.nickname().into(),
-            user_displayname: "".into(),
-        }),
+        Action::GetStatus {} => Some(ActionData::GetStatus(OneBotStatus {
+            good: true,
+            bots: Bot::list().into_iter().map(BotStatus::from).collect(),
+        })),
+        Action::GetSupportedActions {} => {
+            Some(ActionData::support_actions(ctx.in_flight.is_some()))
+        }
+        Action::GetVersion {} => Some(ActionData::version()),
+        Action::GetSelfInfo {} => {
+            let bot = get_bot(&bot_self, ctx)?;
+
+            let nickname = bot.nickname();
+
+            Some(ActionData::GetSelfInfo {
+                user_id: bot.id().to_string(),
+                user_displayname: nickname.clone(),
+                user_name: nickname,
+                avatar: user_avatar(bot.id()),
+            })
+        }
         Action::GetUserInfo { user_id } => {
-            let id = id_parse!(&user_id, echo);
-            let friend = get_friend!(bot, id, echo);
+            let bot = get_bot(&bot_self, ctx)?;
+            let id = parse_id(&user_id)?;
+            // Atri暂未提供陌生人资料查询, 非好友时从机器人所在群的群员中查找
+            let info = match bot.find_friend(id) {
+                Some(friend) => UserInfo::from(friend),
+                None => bot
+                    .groups()
+                    .into_iter()
+                    .find_map(|group| group.find_member(id))
+                    .map(UserInfo::from)
+                    .ok_or(ActionError::UserNotFound)?,
+            };
 
-            Some(ActionData::GetUserInfo(UserInfo {
-                user_id,
-                user_name: friend.nickname().into(),
-                user_display_name: friend.nickname().into(),
-                user_remark: friend.nickname().into(),
-            }))
+            Some(ActionData::GetUserInfo(info))
         }
-        Action::GetFriendList {} => Some(ActionData::GetFriendList(
-            bot.friends().into_iter().map(UserInfo::from).collect(),
-        )),
+        Action::GetFriendList { no_cache } => {
+            let bot = get_bot(&bot_self, ctx)?;
+
+            let cached = (!no_cache)
+                .then(|| ctx.contacts.friends(bot.id()))
+                .flatten();
+            let friends = match cached {
+                Some(friends) => friends,
+                None => {
+                    let friends: Vec<UserInfo> =
+                        bot.friends().into_iter().map(UserInfo::from).collect();
+                    ctx.contacts.set_friends(bot.id(), friends.clone());
+                    friends
+                }
+            };
 
+            Some(ActionData::GetFriendList(friends))
+        }
         Action::GetGroupInfo { group_id } => {
-            let id = id_parse!(&group_id, echo);
-            let group = get_group!(bot, id, echo);
-
-            Some(ActionData::GetGroupInfo(GroupInfo {
-                group_id,
-                group_name: group.name().into(),
-            }))
-        }
-        Action::GetGroupList {} => Some(ActionData::GetGroupList(
-            bot.groups().into_iter().map(GroupInfo::from).collect(),
-        )),
-        Action::GetGroupMemberInfo { group_id, user_id } => {
-            let g_id = id_parse!(&group_id, echo);
-            let u_id = id_parse!(&user_id, echo);
-
-            let group = get_group!(bot, g_id, echo);
-
-            let member = if let Some(named) = group.get_named_member(u_id).await {
-                named
-            } else {
-                return ActionResponse {
-                    status: ActionStatus::Failed,
-                    retcode: 35004,
-                    data: None,
-                    message: "群员不存在".into(),
-                    echo,
-                };
+            let bot = get_bot(&bot_self, ctx)?;
+            let id = parse_id(&group_id)?;
+            let group = get_group(&bot, id)?;
+
+            Some(ActionData::GetGroupInfo(GroupInfo::from(group)))
+        }
+        Action::GetGroupList { no_cache } => {
+            let bot = get_bot(&bot_self, ctx)?;
+
+            let cached = (!no_cache).then(|| ctx.contacts.groups(bot.id())).flatten();
+            let groups = match cached {
+                Some(groups) => groups,
+                None => {
+                    let groups: Vec<GroupInfo> =
+                        bot.groups().into_iter().map(GroupInfo::from).collect();
+                    ctx.contacts.set_groups(bot.id(), groups.clone());
+                    groups
+                }
             };
 
-            Some(ActionData::GetGroupMemberInfo(GroupMemberInfo {
-                user_id,
-                user_name: member.nickname().to_string(),
-                user_displayname: member.card_name().to_string(),
-            }))
+            Some(ActionData::GetGroupList(groups))
         }
-        Action::GetGroupMemberList { group_id } => {
-            let id = id_parse!(&group_id, echo);
+        Action::GetGroupMemberInfo {
+            group_id,
+            user_id,
+            no_cache,
+        } => {
+            let bot = get_bot(&bot_self, ctx)?;
+            let g_id = parse_id(&group_id)?;
+            let u_id = parse_id(&user_id)?;
 
-            let group = get_group!(bot, id, echo);
+            let group = get_group(&bot, g_id)?;
 
-            Some(ActionData::GetGroupMemberList(
-                group
-                    .members()
+            let cached = (!no_cache)
+                .then(|| ctx.contacts.member(bot.id(), g_id, u_id))
+                .flatten();
+            let member = match cached {
+                Some(member) => member,
+                None => group
+                    .get_named_member(u_id)
                     .await
-                    .into_iter()
                     .map(GroupMemberInfo::from)
-                    .collect(),
-            ))
+                    .ok_or(ActionError::MemberNotFound)?,
+            };
+
+            Some(ActionData::GetGroupMemberInfo(member))
+        }
+        Action::GetGroupMemberList {
+            group_id,
+            no_cache,
+            offset,
+            limit,
+            name,
+        } => {
+            let bot = get_bot(&bot_self, ctx)?;
+            let id = parse_id(&group_id)?;
+
+            let group = get_group(&bot, id)?;
+            let members = group_members(ctx, &group, no_cache).await;
+
+            if offset.is_none() && limit.is_none() && name.is_none() {
+                return Ok(Some(ActionData::GetGroupMemberList(members)));
+            }
+
+            let (total, members) = page_members(members, offset, limit, name.as_deref());
+            Some(ActionData::QQGetGroupMemberPage { total, members })
         }
         Action::SetGroupName {
             group_id,
             group_name,
         } => {
-            let id = id_parse!(&group_id, echo);
-            let group = get_group!(bot, id, echo);
+            let bot = get_bot(&bot_self, ctx)?;
+            let id = parse_id(&group_id)?;
+            let group = get_group(&bot, id)?;
 
-            if let Err(e) = group.change_name(&group_name).await {
-                return ActionResponse::from_err(e, 35012, echo);
-            }
+            group
+                .change_name(&group_name)
+                .await
+                .map_err(ActionError::SetGroupNameFailed)?;
 
             None
         }
         Action::LeaveGroup { group_id } => {
-            let id = id_parse!(&group_id, echo);
-            let group = get_group!(bot, id, echo);
+            let bot = get_bot(&bot_self, ctx)?;
+            let id = parse_id(&group_id)?;
+            let group = get_group(&bot, id)?;
 
             if !group.quit().await {
-                return ActionResponse {
-                    status: ActionStatus::Failed,
-                    retcode: 35021,
-                    data: None,
-                    message: "未退出群, 可能是已经退出".into(),
-                    echo,
-                };
+                return Err(ActionError::LeaveGroupFailed);
             }
 
             None
         }
         Action::SendMessage(msg) => {
-            match msg {
+            let bot = get_bot(&bot_self, ctx)?;
+            let (target, message) = match msg {
                 OneBotMessageAction::Group { message, group_id } => {
-                    let id = id_parse!(&group_id, echo);
-                    let group = get_group!(bot, id, echo);
+                    let id = parse_id(&group_id)?;
+                    get_group(&bot, id)?;
+                    (SendTarget::Group(id), message)
                 }
-                OneBotMessageAction::Private { message, user_id } => {
-                    let id = id_parse!(&user_id, echo);
-                }
-                OneBotMessageAction::Channel {
+                OneBotMessageAction::Private {
                     message,
-                    guild_id,
-                    channel_id,
+                    user_id,
+                    group_id,
                 } => {
-                    return ActionResponse {
-                        status: ActionStatus::Failed,
-                        retcode: 10002,
-                        data: None,
-                        message: "暂不支持发送频道消息".into(),
-                        echo,
-                    };
+                    let id = parse_id(&user_id)?;
+                    if bot.find_friend(id).is_none() {
+                        if let Some(group_id) = group_id {
+                            let group = get_group(&bot, parse_id(&group_id)?)?;
+                            group.find_member(id).ok_or(ActionError::MemberNotFound)?;
+
+                            // Atri暂未提供发送临时会话消息的接口, 参数校验通过后仍须拒绝
+                            return Err(ActionError::UnsupportedParam(
+                                "暂不支持发送临时会话消息".into(),
+                            ));
+                        }
+
+                        return Err(ActionError::FriendNotFound);
+                    }
+                    (SendTarget::Friend(id), message)
+                }
+                OneBotMessageAction::Channel { .. } => {
+                    return Err(ActionError::UnsupportedParam("暂不支持发送频道消息".into()));
                 }
+            };
+
+            let elements = message.clone();
+            let (result, report) = ctx
+                .scheduler
+                .schedule(bot.id(), target, || send_message(&bot, target, message))
+                .await
+                .map_err(|e| match e {
+                    ScheduleError::QueueFull { length } => ActionError::SendQueueFull(length),
+                    ScheduleError::Timeout { position } => ActionError::SendQueueTimeout(position),
+                })?;
+            let alt_message = result?;
+
+            let message = OneBotMessage {
+                message_id: uuid::Uuid::new_v4().to_string(),
+                message: elements,
+                alt_message,
+            };
+            let message_id = message.message_id.clone();
+            let time = sys_time();
+
+            let event = match target {
+                SendTarget::Group(id) => OneBotMessageEvent::Group {
+                    message,
+                    group_id: id.to_string(),
+                    user_id: bot.id().to_string(),
+                },
+                SendTarget::Friend(id) => OneBotMessageEvent::Private {
+                    message,
+                    user_id: id.to_string(),
+                },
+            };
+            ctx.messages.insert(
+                bot.id(),
+                CachedMessage {
+                    time,
+                    outgoing: true,
+                    event,
+                },
+            );
+
+            Some(ActionData::SendMessage {
+                message_id,
+                time,
+                queue_position: report.position,
+                queue_wait: report.waited,
+            })
+        }
+        Action::QQGetMessage { message_id } => {
+            let bot = get_bot(&bot_self, ctx)?;
+
+            let msg = ctx
+                .messages
+                .get(bot.id(), &message_id)
+                .ok_or(ActionError::MessageNotFound)?;
+
+            Some(ActionData::QQGetMessage(msg))
+        }
+        Action::QQGetGroupHistory {
+            group_id,
+            message_id,
+            time,
+            limit,
+        } => {
+            let bot = get_bot(&bot_self, ctx)?;
+            let id = parse_id(&group_id)?;
+            get_group(&bot, id)?;
+
+            // Atri暂未提供漫游消息, 仅从本地消息缓存中获取
+            let limit = limit.unwrap_or(20);
+            let messages = ctx
+                .messages
+                .group_history(
+                    bot.id(),
+                    &id.to_string(),
+                    message_id.as_deref(),
+                    time,
+                    limit,
+                )
+                .ok_or(ActionError::MessageNotFound)?;
+
+            Some(ActionData::QQGetGroupHistory {
+                incomplete: messages.len() < limit,
+                messages,
+            })
+        }
+        Action::QQSetGroupMemberCard {
+            group_id,
+            user_id,
+            card,
+        } => {
+            let bot = get_bot(&bot_self, ctx)?;
+            let g_id = parse_id(&group_id)?;
+            let u_id = parse_id(&user_id)?;
+            let group = get_group(&bot, g_id)?;
+
+            let member = group
+                .get_named_member(u_id)
+                .await
+                .ok_or(ActionError::MemberNotFound)?;
+
+            member
+                .change_card_name(&card)
+                .await
+                .map_err(ActionError::SetMemberCardFailed)?;
+
+            None
+        }
+        Action::QQCancel { echo } => {
+            let in_flight = ctx.in_flight.as_ref().ok_or_else(|| {
+                ActionError::UnsupportedAction("qq.cancel, 仅WebSocket连接支持取消动作".into())
+            })?;
+            if !in_flight.cancel(&echo) {
+                return Err(ActionError::RequestNotFound);
             }
 
             None
         }
-        or => {
-            return ActionResponse {
-                status: ActionStatus::Failed,
-                retcode: 20001,
-                data: None,
-                message: format!("未知动作: {:?}", or),
-                echo,
-            };
+        Action::QQBatch { requests } => {
+            Some(ActionData::QQBatch(handle_batch(requests, ctx).await?))
+        }
+        Action::Extension { name, params } => {
+            Some(ActionData::Extension(extension::call(name, params).await?))
+        }
+        action @ (Action::GetLatestEvents { .. } | Action::DeleteMessage { .. }) => {
+            return Err(ActionError::UnsupportedAction(action.name().into()));
         }
     };
 
-    ActionResponse {
-        status: ActionStatus::Ok,
-        retcode: 0,
-        data,
-        message: "".to_string(),
-        echo,
+    Ok(data)
+}
+
+/// 动作所需的机器人账号, 用作限流键
+///
+/// 先解析机器人再限流, 使同一账号的不同写法共用一个令牌桶
+fn limit_bot_id(
+    action: &Action,
+    bot_self: &Option<BotData>,
+    ctx: &ActionContext,
+) -> Result<Option<i64>, ActionError> {
+    match action {
+        Action::GetSupportedActions {}
+        | Action::GetStatus {}
+        | Action::GetVersion {}
+        | Action::QQCancel { .. }
+        | Action::QQBatch { .. } => Ok(None),
+        // 扩展动作与不支持的动作
+        _ if !Action::SUPPORTED.contains(&action.name()) => Ok(None),
+        _ => get_bot(bot_self, ctx).map(|bot| Some(bot.id())),
+    }
+}
+
+/// 发送消息, 成功时返回消息的替代表示
+async fn send_message(
+    bot: &Bot,
+    target: SendTarget,
+    message: Vec<MessageElement>,
+) -> Result<String, ActionError> {
+    let chain = to_message_chain(message)?;
+    let alt_message = chain.to_string();
+
+    let result = match target {
+        SendTarget::Group(id) => get_group(bot, id)?.send_message(chain).await,
+        SendTarget::Friend(id) => get_friend(bot, id)?.send_message(chain).await,
+    };
+
+    result
+        .map(|_| alt_message)
+        .map_err(ActionError::PlatformError)
+}
+
+/// 获取群员列表, 优先使用缓存
+async fn group_members(ctx: &ActionContext, group: &Group, no_cache: bool) -> Vec<GroupMemberInfo> {
+    let bot_id = group.bot().id();
+
+    if !no_cache {
+        if let Some(members) = ctx.contacts.members(bot_id, group.id()) {
+            return members;
+        }
     }
+
+    let members: Vec<GroupMemberInfo> = group
+        .members()
+        .await
+        .into_iter()
+        .map(GroupMemberInfo::from)
+        .collect();
+    ctx.contacts
+        .set_members(bot_id, group.id(), members.clone());
+
+    members
+}
+
+/// 按昵称或群名片包含的文本过滤群员并分页, 返回过滤后的群员数与当前页的群员
+pub fn page_members(
+    members: Vec<GroupMemberInfo>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    name: Option<&str>,
+) -> (usize, Vec<GroupMemberInfo>) {
+    let members: Vec<GroupMemberInfo> = members
+        .into_iter()
+        .filter(|m| {
+            name.is_none_or(|name| m.user_name.contains(name) || m.user_displayname.contains(name))
+        })
+        .collect();
+
+    let total = members.len();
+    let members = members
+        .into_iter()
+        .skip(offset.unwrap_or(0))
+        .take(limit.unwrap_or(usize::MAX))
+        .collect();
+
+    (total, members)
+}
+
+fn parse_id(id: &str) -> Result<i64, ActionError> {
+    i64::from_str(id).map_err(|e| ActionError::BadParam(format!("{}: {}", id, e)))
+}
+
+/// 获取动作对应的机器人
+///
+/// 请求未指定`self`时, 依次使用连接绑定的机器人与唯一在线的机器人
+pub fn get_bot(bot_self: &Option<BotData>, ctx: &ActionContext) -> Result<Bot, ActionError> {
+    let bot_id = match (bot_self, ctx.default_bot) {
+        (Some(b), _) => parse_id(&b.user_id)?,
+        (None, Some(id)) => id,
+        (None, None) => {
+            let mut bots = Bot::list();
+            return match bots.len() {
+                0 => Err(ActionError::UnknownSelf),
+                1 => Ok(bots.remove(0)),
+                _ => Err(ActionError::WhoAmI),
+            };
+        }
+    };
+
+    Bot::find(bot_id).ok_or(ActionError::UnknownSelf)
+}
+
+fn get_group(bot: &Bot, id: i64) -> Result<Group, ActionError> {
+    bot.find_group(id).ok_or(ActionError::GroupNotFound)
+}
+
+fn get_friend(bot: &Bot, id: i64) -> Result<Friend, ActionError> {
+    bot.find_friend(id).ok_or(ActionError::FriendNotFound)
 }
@@ -1,7 +1,13 @@
-use actix_web::{post, web, Responder};
+use std::sync::Arc;
 
-use crate::data::action::{ActionRequest, ActionResponse};
-use crate::handler::handle_action;
+use actix_web::{post, HttpRequest, HttpResponse, Responder};
+
+use crate::config::MessageFormat;
+use crate::data::action::ActionResponse;
+use crate::error::ActionError;
+use crate::handler::{handle_action, handle_batch, parse_request, ActionContext};
+use crate::v11::action::{V11Request, V11Response};
+use crate::v11::{self, MessageIds};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize)]
@@ -11,14 +17,78 @@ pub struct Authorization {
 
 #[derive(Serialize, Deserialize)]
 pub struct BotQuery {
-    bot_id: Option<i64>,
+    pub bot_id: Option<i64>,
+    pub message_format: Option<MessageFormat>,
 }
 
 #[post("/onebot12/http")]
-pub async fn onebot_http(req: String) -> impl Responder {
-    let rsp = match serde_json::from_str::<ActionRequest>(&req) {
-        Ok(req) => handle_action(req).await,
-        Err(e) => ActionResponse::from_err(e, 10001, None),
+pub async fn onebot_http(req: HttpRequest, body: String) -> impl Responder {
+    let ctx = if let Some(ctx) = req.app_data::<Arc<ActionContext>>() {
+        ctx.with_query(req.query_string())
+    } else {
+        return HttpResponse::ExpectationFailed().finish();
+    };
+
+    // 请求数组按批量请求处理
+    if body.trim_start().starts_with('[') {
+        let rsp = match serde_json::from_str(&body) {
+            Ok(requests) => handle_batch(requests, &ctx).await,
+            Err(e) => Err(ActionError::BadRequest(e.to_string())),
+        };
+
+        return match rsp {
+            Ok(responses) => HttpResponse::Ok().json(responses),
+            Err(e) => HttpResponse::Ok().json(ActionResponse::from_err(e, None)),
+        };
+    }
+
+    let rsp = match parse_request(&body, ctx.message_format) {
+        Ok(req) => handle_action(req, &ctx).await,
+        Err((e, echo)) => ActionResponse::from_err(e, echo),
+    };
+    HttpResponse::Ok().json(rsp)
+}
+
+/// OneBot 11的HTTP接口, 动作名为路径, 参数为请求体
+#[post("/{action}")]
+pub async fn onebot11_http(req: HttpRequest, body: String) -> impl Responder {
+    let ctx = if let Some(ctx) = req.app_data::<Arc<ActionContext>>() {
+        ctx.with_query(req.query_string())
+    } else {
+        return HttpResponse::ExpectationFailed().finish();
+    };
+
+    let ids = if let Some(ids) = req.app_data::<Arc<MessageIds>>() {
+        Arc::clone(ids)
+    } else {
+        return HttpResponse::ExpectationFailed().finish();
+    };
+
+    let action = req
+        .match_info()
+        .get("action")
+        .unwrap_or_default()
+        .to_owned();
+    let params = if body.trim().is_empty() {
+        serde_json::Value::Null
+    } else {
+        match serde_json::from_str(&body) {
+            Ok(params) => params,
+            Err(e) => {
+                let rsp = V11Response::from_err(
+                    ActionError::BadRequest(e.to_string()),
+                    serde_json::Value::Null,
+                );
+                return HttpResponse::Ok().json(rsp);
+            }
+        }
+    };
+
+    let req = V11Request {
+        action,
+        params,
+        echo: serde_json::Value::Null,
     };
-    web::Json(rsp)
+    let rsp = v11::action::handle_action(req, &ctx, &ids).await;
+    HttpResponse::Ok().json(rsp)
 }
@@ -6,21 +6,34 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
-use crate::config::{AtriOneBotConfig, OneBotServer};
+use crate::config::{AtriOneBotConfig, OneBotProtocol, OneBotServer};
 use actix_web::dev::{ServerHandle, Service};
 use actix_web::http::header::Header;
 use actix_web::{web, App, HttpResponse, HttpServer};
 use actix_web_httpauth::headers::authorization::{Authorization, Bearer};
 use atri_plugin::listener::ListenerGuard;
-use atri_plugin::{error, info, Plugin};
+use atri_plugin::{info, Plugin};
 
-use crate::http::onebot_http;
+use crate::contact_cache::ContactCache;
+use crate::handler::ActionContext;
+use crate::http::{onebot11_http, onebot_http};
+use crate::limiter::RateLimiter;
+use crate::message_cache::MessageCache;
+use crate::scheduler::SendScheduler;
+use crate::v11::MessageIds;
 use crate::websocket::{listener, start_websocket};
 
 mod config;
+mod contact_cache;
 mod data;
+mod error;
+mod extension;
 mod handler;
 mod http;
+mod limiter;
+mod message_cache;
+mod scheduler;
+mod v11;
 mod websocket;
 
 #[atri_plugin::plugin]
@@ -31,11 +44,14 @@ struct AtriOneBot {
 struct WebServer {
     runtime: tokio::runtime::Runtime,
     handles: Vec<ServerHandle>,
+    messages: Arc<MessageCache>,
+    persist_messages: bool,
     _listener: ListenerGuard,
 }
 
 static CONFIG_DIR: &str = "workspaces/atri_onebot";
 static CONFIG_FILE: &str = "config.toml";
+static MESSAGE_CACHE_FILE: &str = "messages.json";
 
 impl Plugin for AtriOneBot {
     fn new() -> Self {
@@ -51,6 +67,7 @@ impl Plugin for AtriOneBot {
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(&path)
             .expect("Cannot open or create config file");
 
@@ -59,7 +76,7 @@ impl Plugin for AtriOneBot {
         drop(f);
 
         let config: AtriOneBotConfig = toml::from_slice(&bytes).unwrap_or_else(|e| {
-            error!("读取配置文件失败: {}", e);
+            atri_plugin::error!("读取配置文件失败: {}", e);
 
             let c = AtriOneBotConfig::default();
             let str = toml::to_string_pretty(&c).unwrap();
@@ -87,21 +104,77 @@ impl Plugin for AtriOneBot {
         if heartbeat.interval <= 0 {
             heartbeat.enabled = false;
         }
+
+        let limiter = Arc::new(RateLimiter::new(config.rate_limit));
+        let scheduler = Arc::new(SendScheduler::new(config.send_queue));
+
+        let timeout = Arc::new(config.timeout);
+        let ws_config = config.websocket;
+        let contacts = Arc::new(ContactCache::new(config.contact_cache));
+        let messages = Arc::new(MessageCache::new(config.message_cache.capacity));
+        let message_ids = Arc::new(MessageIds::new(config.message_cache.capacity));
+        if config.message_cache.persist {
+            let path = PathBuf::from(CONFIG_DIR).join(MESSAGE_CACHE_FILE);
+            if path.exists() {
+                if let Err(e) = messages.load(&path) {
+                    atri_plugin::error!("读取消息缓存失败: {}", e);
+                }
+            }
+
+            let interval = config.message_cache.save_interval;
+            if interval > 0 {
+                let messages = Arc::clone(&messages);
+                rt.spawn(async move {
+                    loop {
+                        tokio::time::sleep(Duration::from_secs(interval)).await;
+
+                        let messages = Arc::clone(&messages);
+                        let path = path.clone();
+                        let result =
+                            tokio::task::spawn_blocking(move || messages.save_if_dirty(&path))
+                                .await;
+                        if let Ok(Err(e)) = result {
+                            atri_plugin::error!("保存消息缓存失败: {}", e);
+                        }
+                    }
+                });
+            }
+        }
         for server in config.servers {
             match server {
                 OneBotServer::WebSocket {
                     host,
                     port,
                     access_token,
+                    default_bot,
+                    protocol,
+                    message_format,
                 } => {
                     let server_tx = tx.clone();
 
+                    let ctx = Arc::new(ActionContext {
+                        access_token: access_token.clone(),
+                        default_bot,
+                        limiter: Arc::clone(&limiter),
+                        scheduler: Arc::clone(&scheduler),
+                        messages: Arc::clone(&messages),
+                        contacts: Arc::clone(&contacts),
+                        message_format,
+                        batch: config.batch,
+                        timeout: Arc::clone(&timeout),
+                        in_flight: None,
+                    });
                     let token = Arc::new(access_token);
+                    let message_ids = Arc::clone(&message_ids);
 
                     let http_server = HttpServer::new(move || {
                         let token = Arc::clone(&token);
+                        let server_tx = server_tx.clone();
 
                         App::new()
+                            .app_data(Arc::clone(&ctx))
+                            .app_data(Arc::clone(&message_ids))
+                            .app_data(protocol)
                             .wrap_fn(move |req, routing| {
                                 let f: Box<dyn Future<Output = _>> = if let Some(token) = &*token {
                                     let correct = &**token;
@@ -142,13 +215,28 @@ impl Plugin for AtriOneBot {
                                     pin.await
                                 }
                             })
-                            .service(
-                                web::resource("/onebot12/websocket")
-                                    .route(web::get().to(start_websocket))
-                                    .app_data(server_tx.clone())
-                                    .app_data(heartbeat),
-                            )
-                            .service(onebot_http)
+                            .configure(move |cfg| match protocol {
+                                OneBotProtocol::V12 => {
+                                    cfg.service(
+                                        web::resource("/onebot12/websocket")
+                                            .route(web::get().to(start_websocket))
+                                            .app_data(server_tx)
+                                            .app_data(heartbeat)
+                                            .app_data(ws_config),
+                                    )
+                                    .service(onebot_http);
+                                }
+                                OneBotProtocol::V11 => {
+                                    cfg.service(
+                                        web::resource("/")
+                                            .route(web::get().to(start_websocket))
+                                            .app_data(server_tx)
+                                            .app_data(heartbeat)
+                                            .app_data(ws_config),
+                                    )
+                                    .service(onebot11_http);
+                                }
+                            })
                             .default_service(web::to(|| async { "Unknown" }))
                     })
                     .bind((host, port))
@@ -167,11 +255,13 @@ impl Plugin for AtriOneBot {
         }
 
         let tx = tx.clone();
-        let guard = listener(tx);
+        let guard = listener(tx, Arc::clone(&messages), contacts);
 
         self.server = Some(WebServer {
             runtime: rt,
             handles,
+            messages,
+            persist_messages: config.message_cache.persist,
             _listener: guard,
         });
 
@@ -183,20 +273,53 @@ impl Drop for AtriOneBot {
     fn drop(&mut self) {
         if let Some(server) = mem::take(&mut self.server) {
             server.handles.iter().for_each(|handle| {
-                let _ = handle.stop(true);
+                drop(handle.stop(true));
             });
 
             server.runtime.shutdown_timeout(Duration::from_millis(800));
+
+            if server.persist_messages {
+                let path = PathBuf::from(CONFIG_DIR).join(MESSAGE_CACHE_FILE);
+                if let Err(e) = server.messages.save(&path) {
+                    atri_plugin::error!("保存消息缓存失败: {}", e);
+                }
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
     use actix_web::{get, web, App, HttpServer, Responder};
+    use atri_plugin::message::meta::{MessageMetadata, Reply};
     use serde_json::json;
 
-    use crate::data::action::ActionRequest;
+    use crate::config::{
+        BatchConfig, BucketConfig, ContactCacheConfig, MessageFormat, RateLimitConfig,
+        RateLimitMode, SendQueueConfig, TargetQueueConfig, TimeoutConfig,
+    };
+    use crate::contact_cache::ContactCache;
+    use crate::data::action::{Action, ActionData, ActionRequest, OneBotMessageAction};
+    use crate::data::contact::{GroupInfo, GroupMemberInfo};
+    use crate::data::event::{OneBotEvent, OneBotTypedEvent};
+    use crate::data::message::{
+        to_message_chain, Chat, MessageElement, OneBotMessage, OneBotMessageEvent,
+    };
+    use crate::extension;
+    use crate::handler::{
+        handle_action, handle_batch, handle_cancellable, page_members, parse_request, ActionContext,
+    };
+    use crate::limiter::{LimitKey, RateLimiter};
+    use crate::message_cache::{CachedMessage, MessageCache};
+    use crate::scheduler::{ScheduleError, SendScheduler, SendTarget};
+    use crate::v11::action::V11Action;
+    use crate::v11::cq;
+    use crate::v11::event::V11Event;
+    use crate::v11::message::{to_elements, V11Message};
+    use crate::v11::MessageIds;
 
     #[test]
     fn test_server() {
@@ -206,7 +329,7 @@ mod tests {
         }
 
         actix_web::rt::Runtime::new().unwrap().block_on(async {
-            let _ = HttpServer::new(|| {
+            let _server = HttpServer::new(|| {
                 App::new()
                     .service(hello)
                     .default_service(web::to(|| async { "Where are u" }))
@@ -332,6 +455,27 @@ mod tests {
             serde_json::from_value::<ActionRequest>(group_member_list_req).unwrap()
         );
 
+        let group_member_page_req = json!({
+                "action": "get_group_member_list",
+                "params": {
+                "group_id": "114514",
+                "qq.offset": 100,
+                "qq.limit": 50,
+                "qq.name": "atri"
+            }
+        });
+
+        let req = serde_json::from_value::<ActionRequest>(group_member_page_req).unwrap();
+        assert!(matches!(
+            req.action,
+            Action::GetGroupMemberList {
+                offset: Some(100),
+                limit: Some(50),
+                ref name,
+                ..
+            } if name.as_deref() == Some("atri")
+        ));
+
         let set_group_name_req = json!({
                 "action": "set_group_name",
                 "params": {
@@ -345,4 +489,836 @@ mod tests {
             serde_json::from_value::<ActionRequest>(set_group_name_req).unwrap()
         );
     }
+
+    #[test]
+    fn member_page() {
+        let member = |id: &str, card: &str| GroupMemberInfo {
+            user_id: id.into(),
+            user_name: format!("user{}", id),
+            user_displayname: card.into(),
+        };
+        let members = vec![
+            member("1", "atri"),
+            member("2", ""),
+            member("3", "atri fan"),
+            member("4", "atri club"),
+        ];
+        let ids = |(total, members): (usize, Vec<GroupMemberInfo>)| {
+            let ids: Vec<String> = members.into_iter().map(|m| m.user_id).collect();
+            (total, ids)
+        };
+
+        assert_eq!(
+            ids(page_members(members.clone(), Some(1), Some(2), None)),
+            (4, vec!["2".into(), "3".into()])
+        );
+        assert_eq!(
+            ids(page_members(members.clone(), Some(1), None, Some("atri"))),
+            (3, vec!["3".into(), "4".into()])
+        );
+        assert_eq!(
+            ids(page_members(members.clone(), None, Some(0), Some("user2"))),
+            (1, vec![])
+        );
+        assert_eq!(
+            ids(page_members(members, Some(10), None, None)),
+            (4, vec![])
+        );
+    }
+
+    #[test]
+    fn supported_actions() {
+        assert!(Action::SUPPORTED.contains(&"get_supported_actions"));
+        assert!(Action::SUPPORTED.contains(&"send_message"));
+        assert!(!Action::SUPPORTED.contains(&"get_latest_events"));
+
+        for &name in Action::SUPPORTED {
+            let req = json!({
+                "action": name,
+                "params": {}
+            });
+
+            if let Ok(req) = serde_json::from_value::<ActionRequest>(req) {
+                assert_eq!(req.action.name(), name);
+            }
+        }
+
+        let listed = |cancellable| match ActionData::support_actions(cancellable) {
+            ActionData::GetSupportActions(actions) => actions,
+            _ => unreachable!(),
+        };
+        assert!(listed(true).contains(&"qq.cancel"));
+        assert!(!listed(false).contains(&"qq.cancel"));
+
+        for &name in crate::v11::action::SUPPORTED {
+            let err = serde_json::from_value::<V11Action>(json!({
+                "action": name,
+                "params": {}
+            }))
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+            assert!(!err.contains("unknown variant"), "{}: {}", name, err);
+        }
+    }
+
+    #[test]
+    fn request_errors() {
+        let retcode = |json: &str| {
+            parse_request(json, MessageFormat::Array)
+                .map(|_| ())
+                .map_err(|(e, echo)| (e.retcode(), echo))
+        };
+
+        assert_eq!(retcode("{"), Err((10001, None)));
+        assert_eq!(
+            retcode(r#"{"params": {}, "echo": "1"}"#),
+            Err((10001, Some("1".into())))
+        );
+        assert_eq!(
+            retcode(r#"{"action": "qq.unknown", "params": {}, "echo": "2"}"#),
+            Err((10002, Some("2".into())))
+        );
+        assert_eq!(
+            retcode(r#"{"action": "get_user_info", "params": {}, "echo": "3"}"#),
+            Err((10003, Some("3".into())))
+        );
+        assert_eq!(
+            retcode(r#"{"action": "get_user_info", "params": {"user_id": "114514"}}"#),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn segments() {
+        let message: Vec<MessageElement> = serde_json::from_value(json!([
+            { "type": "text", "data": { "text": "114514" } },
+            { "type": "qq.unknown", "data": {} }
+        ]))
+        .unwrap();
+
+        assert_eq!(message[1].name(), "qq.unknown");
+        assert!(serde_json::from_value::<MessageElement>(
+            json!({ "type": "qq.face", "data": { "id": 178 } })
+        )
+        .is_err());
+
+        let err = to_message_chain(message).err().unwrap();
+        assert_eq!(err.retcode(), 10005);
+    }
+
+    #[test]
+    fn message_cache() {
+        let msg = |id: &str| CachedMessage {
+            time: 0.0,
+            outgoing: false,
+            event: OneBotMessageEvent::Group {
+                message: OneBotMessage {
+                    message_id: id.into(),
+                    message: vec![MessageElement::Text { text: id.into() }],
+                    alt_message: id.into(),
+                },
+                group_id: "123456".into(),
+                user_id: "10000".into(),
+            },
+        };
+
+        let cache = MessageCache::new(2);
+        cache.insert(1, msg("a"));
+        cache.insert(1, msg("b"));
+        cache.insert(2, msg("c"));
+        cache.insert(1, msg("d"));
+
+        assert!(cache.get(1, "a").is_none());
+        assert!(cache.get(1, "b").is_some());
+        assert!(cache.get(1, "c").is_none());
+        assert!(cache.get(2, "c").is_some());
+
+        let history = |before_id, before_time| {
+            cache
+                .group_history(1, "123456", before_id, before_time, 10)
+                .unwrap_or_default()
+                .iter()
+                .map(|msg| msg.message_id().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(history(None, None), ["b", "d"]);
+        assert_eq!(history(Some("d"), None), ["b"]);
+        assert!(history(Some("a"), None).is_empty());
+        assert!(cache
+            .group_history(1, "123456", Some("a"), None, 10)
+            .is_none());
+        assert!(history(None, Some(0.0)).is_empty());
+
+        let path = std::env::temp_dir().join("atri_onebot_message_cache.json");
+        cache.save(&path).unwrap();
+
+        let loaded = MessageCache::new(2);
+        loaded.load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(loaded.get(1, "d").unwrap().message_id(), "d");
+        assert!(loaded.get(2, "c").is_some());
+
+        // 回复元素中的消息ID可以在缓存中找到被回复的消息
+        let meta = MessageMetadata {
+            seqs: vec![4242],
+            ..MessageMetadata::default()
+        };
+        let replied = OneBotMessage::id_of(Chat::Group(123456), &meta);
+        cache.insert(1, msg(&replied));
+
+        let reply = Reply {
+            reply_seq: 4242,
+            sender: 10000,
+            time: 0,
+            elements: vec![],
+        };
+        let MessageElement::Reply { message_id, .. } =
+            MessageElement::reply(Chat::Group(123456), &reply)
+        else {
+            unreachable!();
+        };
+        assert_eq!(cache.get(1, &message_id).unwrap().message_id(), replied);
+        assert_ne!(OneBotMessage::id_of(Chat::Group(654321), &meta), replied);
+
+        // 群号与好友QQ号相同时, 群消息与私聊消息的ID不同
+        let private = OneBotMessage::id_of(Chat::Private(123456), &meta);
+        assert_ne!(private, replied);
+        cache.insert(
+            1,
+            CachedMessage {
+                event: OneBotMessageEvent::Private {
+                    message: OneBotMessage {
+                        message_id: private.clone(),
+                        message: vec![],
+                        alt_message: "".into(),
+                    },
+                    user_id: "123456".into(),
+                },
+                ..msg("")
+            },
+        );
+        let MessageElement::Reply { message_id, .. } =
+            MessageElement::reply(Chat::Private(123456), &reply)
+        else {
+            unreachable!();
+        };
+        assert!(matches!(
+            cache.get(1, &message_id).unwrap().event,
+            OneBotMessageEvent::Private { .. }
+        ));
+        assert!(matches!(
+            cache.get(1, &replied).unwrap().event,
+            OneBotMessageEvent::Group { .. }
+        ));
+
+        cache.save(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        cache.save_if_dirty(&path).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn contact_cache() {
+        let member = |id: &str| GroupMemberInfo {
+            user_id: id.into(),
+            user_name: id.into(),
+            user_displayname: id.into(),
+        };
+
+        let cache = ContactCache::new(ContactCacheConfig::default());
+        cache.set_members(1, 100, vec![member("10"), member("11")]);
+        assert_eq!(cache.members(1, 100).map(|m| m.len()), Some(2));
+        assert!(cache.member(1, 100, 11).is_some());
+        assert!(cache.member(1, 100, 12).is_none());
+
+        cache.set_groups(
+            1,
+            vec![GroupInfo {
+                group_id: "100".into(),
+                group_name: "100".into(),
+                avatar: "".into(),
+            }],
+        );
+        cache.on_group_message(1, 100, 10);
+        assert!(cache.members(1, 100).is_some());
+        cache.on_group_message(1, 100, 12);
+        assert!(cache.members(1, 100).is_none());
+        assert!(cache.groups(1).is_some());
+
+        cache.on_bot_login([1]);
+        cache.set_members(1, 100, vec![member("10")]);
+        cache.on_bot_login([1, 2]);
+        cache.set_members(2, 100, vec![member("10")]);
+        assert!(cache.members(1, 100).is_some());
+        cache.on_bot_login([2]);
+        assert!(cache.members(1, 100).is_none());
+        assert!(cache.members(2, 100).is_some());
+
+        let disabled = ContactCache::new(ContactCacheConfig {
+            enabled: false,
+            ..Default::default()
+        });
+        disabled.set_members(1, 100, vec![member("10")]);
+        assert!(disabled.members(1, 100).is_none());
+    }
+
+    #[test]
+    fn v11() {
+        let ids = MessageIds::new(2);
+        let a = ids.numeric("a");
+        assert_eq!(ids.numeric("a"), a);
+        let b = ids.numeric("b");
+        ids.numeric("c");
+        assert!(ids.get(a).is_none());
+        assert_eq!(ids.get(b).as_deref(), Some("b"));
+
+        let event = OneBotEvent {
+            id: "event".into(),
+            time: 1.5,
+            typed: OneBotTypedEvent::Message(OneBotMessageEvent::Group {
+                message: OneBotMessage {
+                    message_id: "b".into(),
+                    message: vec![
+                        MessageElement::Mention {
+                            user_id: "10000".into(),
+                        },
+                        MessageElement::Text { text: "hi".into() },
+                    ],
+                    alt_message: "@10000 hi".into(),
+                },
+                group_id: "123456".into(),
+                user_id: "3847573".into(),
+            }),
+            sub_type: "",
+            bot_self: None,
+        };
+
+        let event =
+            serde_json::to_value(V11Event::from_event(&event, MessageFormat::Array, &ids).unwrap())
+                .unwrap();
+        assert_eq!(event["post_type"], "message");
+        assert_eq!(event["message_type"], "group");
+        assert_eq!(event["message_id"], b);
+        assert_eq!(event["group_id"], 123456);
+        assert_eq!(event["user_id"], 3847573);
+        assert_eq!(
+            event["message"][0],
+            json!({"type": "at", "data": {"qq": "10000"}})
+        );
+
+        let action = serde_json::from_value::<V11Action>(json!({
+            "action": "send_group_msg",
+            "params": {
+                "group_id": 123456,
+                "message": [
+                    {"type": "at", "data": {"qq": "all"}},
+                    {"type": "reply", "data": {"id": b.to_string()}}
+                ]
+            }
+        }))
+        .unwrap();
+
+        let V11Action::SendGroupMsg { message, .. } = action else {
+            panic!("unexpected action: {:?}", action);
+        };
+        let elems = to_elements(message, false, &ids).unwrap();
+        assert_eq!(elems[0].name(), "mention_all");
+        assert!(matches!(&elems[1], MessageElement::Reply { message_id, .. } if message_id == "b"));
+
+        let bad_reply = serde_json::from_value::<V11Message>(json!([
+            {"type": "reply", "data": {"id": a.to_string()}}
+        ]))
+        .unwrap();
+        assert!(to_elements(bad_reply, false, &ids).is_err());
+    }
+
+    #[test]
+    fn cq_code() {
+        let cq = "[CQ:at,qq=123]hello &#91;world&#93; &amp; [CQ:image,file=a&#44;b.jpg]";
+        let elems = cq::to_elements(cq).unwrap();
+
+        assert!(matches!(&elems[0], MessageElement::Mention { user_id } if user_id == "123"));
+        assert!(matches!(&elems[1], MessageElement::Text { text } if text == "hello [world] & "));
+        assert!(matches!(&elems[2], MessageElement::Image { file_id } if file_id == "a,b.jpg"));
+
+        assert_eq!(cq::from_elements(&elems), cq);
+
+        assert_eq!(
+            cq::to_elements("[CQ:at,qq=123").unwrap_err().retcode(),
+            10006
+        );
+        assert_eq!(
+            cq::to_elements("[CQ:poke,qq=123]").unwrap_err().retcode(),
+            10005
+        );
+        assert_eq!(
+            cq::to_elements("[CQ:face,id=14]").unwrap_err().retcode(),
+            10005
+        );
+        assert_eq!(cq::to_elements("[CQ:at]").unwrap_err().retcode(), 10006);
+        assert_eq!(
+            cq::to_elements("[CQ:location,lat=1]")
+                .unwrap_err()
+                .retcode(),
+            10006
+        );
+
+        let req = parse_request(
+            r#"{"action": "send_message", "params": {"detail_type": "group", "group_id": "1", "message": "[CQ:at,qq=all] hi"}}"#,
+            MessageFormat::String,
+        )
+        .unwrap();
+        let Action::SendMessage(OneBotMessageAction::Group { message, .. }) = req.action else {
+            panic!("unexpected action: {:?}", req.action);
+        };
+        assert_eq!(message[0].name(), "mention_all");
+    }
+
+    fn test_context() -> ActionContext {
+        ActionContext {
+            access_token: None,
+            default_bot: None,
+            limiter: Arc::new(RateLimiter::new(RateLimitConfig::default())),
+            scheduler: Arc::new(SendScheduler::new(SendQueueConfig::default())),
+            messages: Arc::new(MessageCache::new(10)),
+            contacts: Arc::new(ContactCache::new(ContactCacheConfig::default())),
+            message_format: MessageFormat::Array,
+            batch: BatchConfig {
+                concurrency: 2,
+                max_requests: 4,
+            },
+            timeout: Arc::new(TimeoutConfig::default()),
+            in_flight: Some(Arc::default()),
+        }
+    }
+
+    #[test]
+    fn batch() {
+        let ctx = test_context();
+
+        actix_web::rt::Runtime::new().unwrap().block_on(async move {
+            let requests = vec![
+                json!({"action": "get_version", "params": {}, "echo": "1"}),
+                json!({"params": {}, "echo": "2"}),
+                json!({"action": "qq.batch", "params": {"requests": []}, "echo": "3"}),
+                json!({"action": "get_supported_actions", "params": {}, "echo": "4"}),
+            ];
+
+            let responses = handle_batch(requests, &ctx).await.unwrap();
+            let results: Vec<_> = responses
+                .iter()
+                .map(|rsp| (rsp.echo.as_deref().unwrap(), rsp.retcode))
+                .collect();
+            assert_eq!(results, [("1", 0), ("2", 10001), ("3", 10001), ("4", 0)]);
+
+            let requests = vec![json!({"action": "get_version", "params": {}}); 5];
+            assert!(handle_batch(requests, &ctx).await.is_err());
+        });
+    }
+
+    #[test]
+    fn timeout_and_cancel() {
+        // 排队模式的限流使第二个请求等待, 以此模拟执行缓慢的动作
+        let mut ctx = test_context();
+        ctx.limiter = Arc::new(RateLimiter::new(RateLimitConfig {
+            mode: RateLimitMode::Queue,
+            default: BucketConfig {
+                capacity: 1,
+                per_second: 1.0,
+            },
+            ..Default::default()
+        }));
+        ctx.timeout = Arc::new(TimeoutConfig {
+            default: 0,
+            actions: [("get_version".to_string(), 100)].into(),
+        });
+
+        let request = |action: &str, params: serde_json::Value, echo: &str| {
+            serde_json::from_value::<ActionRequest>(json!({
+                "action": action,
+                "params": params,
+                "echo": echo,
+            }))
+            .unwrap()
+        };
+
+        actix_web::rt::Runtime::new().unwrap().block_on(async move {
+            let rsp = handle_action(request("get_version", json!({}), "1"), &ctx).await;
+            assert_eq!(rsp.retcode, 0);
+
+            let rsp = handle_action(request("get_version", json!({}), "2"), &ctx).await;
+            assert_eq!((rsp.retcode, rsp.echo.as_deref()), (36003, Some("2")));
+
+            let slow = {
+                let ctx = ctx.clone();
+                let req = request("get_version", json!({}), "3");
+                actix_web::rt::spawn(async move { handle_cancellable(req, &ctx).await })
+            };
+            tokio::time::sleep(Duration::from_millis(20)).await;
+
+            let rsp = handle_cancellable(request("get_version", json!({}), "3"), &ctx).await;
+            assert_eq!((rsp.retcode, rsp.echo.as_deref()), (10001, Some("3")));
+
+            let rsp = handle_action(request("qq.cancel", json!({"echo": "3"}), "4"), &ctx).await;
+            assert_eq!(rsp.retcode, 0);
+            let rsp = slow.await.unwrap();
+            assert_eq!((rsp.retcode, rsp.echo.as_deref()), (36004, Some("3")));
+
+            let rsp = handle_action(request("qq.cancel", json!({"echo": "3"}), "5"), &ctx).await;
+            assert_eq!(rsp.retcode, 35007);
+
+            // HTTP请求之间互不关联, 不支持取消
+            let http = ActionContext {
+                in_flight: None,
+                ..test_context()
+            };
+            let rsp = handle_action(request("qq.cancel", json!({"echo": "3"}), "6"), &http).await;
+            assert_eq!(rsp.retcode, 10002);
+        });
+    }
+
+    #[test]
+    fn batch_cancel() {
+        use std::ffi::{c_char, c_void, CString};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        extern "C" fn handler(_: *const c_char, _: *mut c_void) -> *mut c_char {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            CString::new("{}").unwrap().into_raw()
+        }
+
+        extern "C" fn free(rsp: *mut c_char) {
+            drop(unsafe { CString::from_raw(rsp) });
+        }
+
+        let name = CString::new("test.count").unwrap();
+        assert_eq!(
+            unsafe {
+                extension::atri_onebot_register_action(
+                    name.as_ptr(),
+                    handler,
+                    free,
+                    std::ptr::null_mut(),
+                )
+            },
+            0
+        );
+
+        // 排队模式的限流使批量请求中的动作等待, 以此模拟执行缓慢的子动作
+        let mut ctx = test_context();
+        ctx.limiter = Arc::new(RateLimiter::new(RateLimitConfig {
+            mode: RateLimitMode::Queue,
+            default: BucketConfig {
+                capacity: 1,
+                per_second: 5.0,
+            },
+            ..Default::default()
+        }));
+
+        actix_web::rt::Runtime::new().unwrap().block_on(async move {
+            let req = parse_request(
+                r#"{"action": "test.count", "params": {}}"#,
+                MessageFormat::Array,
+            )
+            .unwrap();
+            assert_eq!(handle_action(req, &ctx).await.retcode, 0);
+            assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+
+            let batch = {
+                let ctx = ctx.clone();
+                let req = serde_json::from_value::<ActionRequest>(json!({
+                    "action": "qq.batch",
+                    "params": {"requests": [{"action": "test.count", "params": {}}]},
+                    "echo": "1",
+                }))
+                .unwrap();
+                actix_web::rt::spawn(async move { handle_cancellable(req, &ctx).await })
+            };
+            tokio::time::sleep(Duration::from_millis(20)).await;
+
+            let req = serde_json::from_value::<ActionRequest>(json!({
+                "action": "qq.cancel",
+                "params": {"echo": "1"},
+            }))
+            .unwrap();
+            assert_eq!(handle_action(req, &ctx).await.retcode, 0);
+            assert_eq!(batch.await.unwrap().retcode, 36004);
+
+            // 取消批量请求后, 等待中的子动作不再执行
+            tokio::time::sleep(Duration::from_millis(400)).await;
+            assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+        });
+    }
+
+    #[test]
+    fn extension() {
+        use std::ffi::{c_char, c_void, CStr, CString};
+
+        extern "C" fn handler(params: *const c_char, _: *mut c_void) -> *mut c_char {
+            let params = unsafe { CStr::from_ptr(params) }.to_str().unwrap();
+            let params: serde_json::Value = serde_json::from_str(params).unwrap();
+            let rsp = match params.get("text") {
+                Some(text) => json!({ "data": { "text": text } }),
+                None => json!({ "retcode": 35100, "message": "缺少text" }),
+            };
+
+            CString::new(rsp.to_string()).unwrap().into_raw()
+        }
+
+        extern "C" fn free(rsp: *mut c_char) {
+            drop(unsafe { CString::from_raw(rsp) });
+        }
+
+        let register = |name: &str| {
+            let name = CString::new(name).unwrap();
+            unsafe {
+                extension::atri_onebot_register_action(
+                    name.as_ptr(),
+                    handler,
+                    free,
+                    std::ptr::null_mut(),
+                )
+            }
+        };
+
+        assert_eq!(register("echo"), -1);
+        assert_eq!(register("qq.echo"), -1);
+        assert_eq!(register("test.echo"), 0);
+        assert_eq!(register("test.echo"), -2);
+
+        let ActionData::GetSupportActions(actions) = ActionData::support_actions(true) else {
+            unreachable!();
+        };
+        assert!(actions.contains(&"test.echo"));
+
+        let ctx = test_context();
+        actix_web::rt::Runtime::new().unwrap().block_on(async move {
+            let req = parse_request(
+                r#"{"action": "test.echo", "params": {"text": "hi"}, "echo": "1"}"#,
+                MessageFormat::Array,
+            )
+            .unwrap();
+            let rsp = serde_json::to_value(handle_action(req, &ctx).await).unwrap();
+            assert_eq!(rsp["data"], json!({ "text": "hi" }));
+            assert_eq!(rsp["echo"], "1");
+
+            let req = parse_request(
+                r#"{"action": "test.echo", "params": {}}"#,
+                MessageFormat::Array,
+            )
+            .unwrap();
+            let rsp = handle_action(req, &ctx).await;
+            assert_eq!((rsp.retcode, rsp.message.as_str()), (35100, "缺少text"));
+        });
+
+        let leaked = extension::find("test.echo").map(|name| name.0.as_ptr());
+        let name = CString::new("test.echo").unwrap();
+        assert_eq!(
+            unsafe { extension::atri_onebot_unregister_action(name.as_ptr()) },
+            0
+        );
+        assert!(parse_request(
+            r#"{"action": "test.echo", "params": {}}"#,
+            MessageFormat::Array
+        )
+        .is_err());
+
+        // 重复注册时复用已泄漏的动作名
+        assert_eq!(register("test.echo"), 0);
+        assert_eq!(
+            extension::find("test.echo").map(|name| name.0.as_ptr()),
+            leaked
+        );
+    }
+
+    #[test]
+    fn extension_unregister_waits() {
+        use std::ffi::{c_char, c_void, CString};
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        static DONE: AtomicBool = AtomicBool::new(false);
+
+        extern "C" fn handler(_: *const c_char, _: *mut c_void) -> *mut c_char {
+            std::thread::sleep(Duration::from_millis(100));
+            DONE.store(true, Ordering::SeqCst);
+            CString::new("{}").unwrap().into_raw()
+        }
+
+        extern "C" fn free(rsp: *mut c_char) {
+            drop(unsafe { CString::from_raw(rsp) });
+        }
+
+        let name = CString::new("test.slow").unwrap();
+        assert_eq!(
+            unsafe {
+                extension::atri_onebot_register_action(
+                    name.as_ptr(),
+                    handler,
+                    free,
+                    std::ptr::null_mut(),
+                )
+            },
+            0
+        );
+
+        let ctx = test_context();
+        actix_web::rt::Runtime::new().unwrap().block_on(async move {
+            let req = parse_request(
+                r#"{"action": "test.slow", "params": {}}"#,
+                MessageFormat::Array,
+            )
+            .unwrap();
+            let call = actix_web::rt::spawn(async move { handle_action(req, &ctx).await });
+            tokio::time::sleep(Duration::from_millis(20)).await;
+
+            // 注销在进行中的调用结束后才返回
+            assert_eq!(
+                unsafe { extension::atri_onebot_unregister_action(name.as_ptr()) },
+                0
+            );
+            assert!(DONE.load(Ordering::SeqCst));
+            assert_eq!(call.await.unwrap().retcode, 0);
+        });
+    }
+
+    #[test]
+    fn rate_limit() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            enabled: true,
+            mode: RateLimitMode::Reject,
+            max_wait: 0,
+            default: BucketConfig {
+                capacity: 2,
+                per_second: 0.001,
+            },
+            send_message: BucketConfig {
+                capacity: 1,
+                per_second: 0.001,
+            },
+        });
+
+        let key = |token: &str, action| LimitKey {
+            access_token: Some(token.into()),
+            bot_id: Some(114514),
+            action,
+        };
+
+        assert!(limiter.acquire(key("a", "get_status")).is_ok());
+        assert!(limiter.acquire(key("a", "get_status")).is_ok());
+        assert!(limiter.acquire(key("a", "get_status")).is_err());
+        assert!(limiter.acquire(key("b", "get_status")).is_ok());
+
+        assert!(limiter.acquire(key("a", "send_message")).is_ok());
+        assert!(limiter.acquire(key("a", "send_message")).is_err());
+
+        let config = RateLimitConfig {
+            enabled: true,
+            default: BucketConfig {
+                capacity: 1,
+                per_second: 10.0,
+            },
+            ..RateLimitConfig::default()
+        };
+        let limiter = RateLimiter::with_prune_interval(config, Duration::ZERO);
+        for bot_id in 0..100 {
+            let key = LimitKey {
+                access_token: None,
+                bot_id: Some(bot_id),
+                action: "get_status",
+            };
+            assert!(limiter.acquire(key).is_ok());
+        }
+        assert_eq!(limiter.bucket_count(), 100);
+
+        std::thread::sleep(Duration::from_millis(150));
+        let key = LimitKey {
+            access_token: None,
+            bot_id: None,
+            action: "get_status",
+        };
+        assert!(limiter.acquire(key).is_ok());
+        assert_eq!(limiter.bucket_count(), 1);
+    }
+
+    #[test]
+    fn send_queue() {
+        let queue = TargetQueueConfig {
+            min_interval: 10,
+            jitter: 0,
+            max_length: 2,
+            timeout: 0,
+        };
+        let scheduler = Arc::new(SendScheduler::new(SendQueueConfig {
+            enabled: true,
+            group: queue,
+            friend: queue,
+        }));
+
+        actix_web::rt::Runtime::new().unwrap().block_on(async {
+            let target = SendTarget::Group(123456);
+
+            let handles: Vec<_> = (1..=3)
+                .map(|i| {
+                    let scheduler = Arc::clone(&scheduler);
+                    actix_web::rt::spawn(async move {
+                        let send = || async move {
+                            tokio::time::sleep(Duration::from_millis(20)).await;
+                            i
+                        };
+                        scheduler.schedule(1, target, send).await
+                    })
+                })
+                .collect();
+
+            let mut results = vec![];
+            for handle in handles {
+                results.push(handle.await.unwrap());
+            }
+
+            let (a, report) = results.remove(0).unwrap();
+            assert_eq!((a, report.position), (1, 0));
+            let (b, report) = results.remove(0).unwrap();
+            assert_eq!((b, report.position), (2, 1));
+            assert!(matches!(
+                results.remove(0),
+                Err(ScheduleError::QueueFull { length: 2 })
+            ));
+
+            let (d, report) = scheduler
+                .schedule(1, SendTarget::Friend(123456), || async { 4 })
+                .await
+                .unwrap();
+            assert_eq!((d, report.position), (4, 0));
+        });
+
+        // 没有排队消息且已过发送间隔的队列会被清理
+        let queue = TargetQueueConfig {
+            min_interval: 100,
+            ..queue
+        };
+        let scheduler = SendScheduler::with_prune_interval(
+            SendQueueConfig {
+                enabled: true,
+                group: queue,
+                friend: queue,
+            },
+            Duration::ZERO,
+        );
+        actix_web::rt::Runtime::new().unwrap().block_on(async {
+            for group_id in 0..100 {
+                let target = SendTarget::Group(group_id);
+                assert!(scheduler.schedule(1, target, || async {}).await.is_ok());
+            }
+            assert_eq!(scheduler.queue_count(), 100);
+
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            let target = SendTarget::Friend(123456);
+            assert!(scheduler.schedule(1, target, || async {}).await.is_ok());
+            assert_eq!(scheduler.queue_count(), 1);
+        });
+    }
 }
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::{BucketConfig, RateLimitConfig, RateLimitMode};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LimitKey {
+    pub access_token: Option<String>,
+    /// 动作所需的机器人账号, 不需要机器人的动作为`None`
+    pub bot_id: Option<i64>,
+    pub action: &'static str,
+}
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+/// 清理空闲令牌桶的间隔
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Buckets {
+    buckets: HashMap<LimitKey, Bucket>,
+    last_prune: Instant,
+}
+
+/// 令牌桶限流器, 按访问令牌, 机器人账号与动作名分别计数
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    prune_interval: Duration,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self::with_prune_interval(config, PRUNE_INTERVAL)
+    }
+
+    pub fn with_prune_interval(config: RateLimitConfig, prune_interval: Duration) -> Self {
+        Self {
+            config,
+            prune_interval,
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                last_prune: Instant::now(),
+            }),
+        }
+    }
+
+    /// 获取一个令牌, 成功则返回需要等待的时间
+    ///
+    /// 超出限制时返回`Err`, 此时不消耗令牌
+    pub fn acquire(&self, key: LimitKey) -> Result<Duration, ()> {
+        if !self.config.enabled {
+            return Ok(Duration::ZERO);
+        }
+
+        let bucket_config = self.bucket_config(key.action);
+        if bucket_config.per_second <= 0.0 {
+            return Ok(Duration::ZERO);
+        }
+
+        let capacity = bucket_config.capacity as f64;
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if now.duration_since(buckets.last_prune) >= self.prune_interval {
+            self.prune(&mut buckets.buckets, now);
+            buckets.last_prune = now;
+        }
+
+        let bucket = buckets.buckets.entry(key).or_insert(Bucket {
+            tokens: capacity,
+            last: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * bucket_config.per_second).min(capacity);
+        bucket.last = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(Duration::ZERO);
+        }
+
+        match self.config.mode {
+            RateLimitMode::Reject => Err(()),
+            RateLimitMode::Queue => {
+                let wait = (1.0 - bucket.tokens) / bucket_config.per_second;
+                let wait = Duration::from_secs_f64(wait);
+
+                if wait > Duration::from_millis(self.config.max_wait) {
+                    return Err(());
+                }
+
+                // 预支令牌, 使排队的请求按先后顺序执行
+                bucket.tokens -= 1.0;
+                Ok(wait)
+            }
+        }
+    }
+
+    /// 移除已回满的令牌桶, 回满的令牌桶与新建的令牌桶等价
+    fn prune(&self, buckets: &mut HashMap<LimitKey, Bucket>, now: Instant) {
+        buckets.retain(|key, bucket| {
+            let config = self.bucket_config(key.action);
+            let elapsed = now.duration_since(bucket.last).as_secs_f64();
+
+            bucket.tokens + elapsed * config.per_second < config.capacity as f64
+        });
+    }
+
+    /// 当前的令牌桶数量
+    #[cfg(test)]
+    pub fn bucket_count(&self) -> usize {
+        let buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        buckets.buckets.len()
+    }
+
+    fn bucket_config(&self, action: &str) -> BucketConfig {
+        match action {
+            "send_message" => self.config.send_message,
+            _ => self.config.default,
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::data::message::OneBotMessageEvent;
+
+/// 缓存的消息, 包括收到的与发出的
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedMessage {
+    pub time: f64,
+    /// 是否为机器人发出的消息, 此时私聊消息的`user_id`为接收者
+    #[serde(rename = "qq.outgoing", default)]
+    pub outgoing: bool,
+    #[serde(flatten)]
+    pub event: OneBotMessageEvent,
+}
+
+impl CachedMessage {
+    pub fn message_id(&self) -> &str {
+        &self.event.message().message_id
+    }
+}
+
+#[derive(Default)]
+struct BotMessages {
+    messages: HashMap<String, CachedMessage>,
+    order: VecDeque<String>,
+}
+
+/// 按机器人分别缓存最近的消息, 超出容量时淘汰最早的消息
+pub struct MessageCache {
+    capacity: usize,
+    bots: Mutex<HashMap<i64, BotMessages>>,
+    /// 上次写入文件后是否有新消息
+    dirty: AtomicBool,
+}
+
+impl MessageCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            bots: Mutex::new(HashMap::new()),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    pub fn insert(&self, bot_id: i64, msg: CachedMessage) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut bots = self.bots.lock().unwrap_or_else(|e| e.into_inner());
+        let bot = bots.entry(bot_id).or_default();
+
+        let id = msg.message_id().to_owned();
+        if bot.messages.insert(id.clone(), msg).is_none() {
+            bot.order.push_back(id);
+        }
+
+        while bot.order.len() > self.capacity {
+            if let Some(id) = bot.order.pop_front() {
+                bot.messages.remove(&id);
+            }
+        }
+
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, bot_id: i64, message_id: &str) -> Option<CachedMessage> {
+        let bots = self.bots.lock().unwrap_or_else(|e| e.into_inner());
+        bots.get(&bot_id)
+            .and_then(|bot| bot.messages.get(message_id))
+            .cloned()
+    }
+
+    /// 获取群中早于指定消息或时间的最近`limit`条消息, 按时间先后排列
+    ///
+    /// 指定的消息不在缓存中时返回`None`
+    pub fn group_history(
+        &self,
+        bot_id: i64,
+        group_id: &str,
+        before_id: Option<&str>,
+        before_time: Option<f64>,
+        limit: usize,
+    ) -> Option<Vec<CachedMessage>> {
+        let bots = self.bots.lock().unwrap_or_else(|e| e.into_inner());
+        let bot = match bots.get(&bot_id) {
+            Some(bot) => bot,
+            None if before_id.is_some() => return None,
+            None => return Some(vec![]),
+        };
+
+        let mut iter = bot.order.iter().rev();
+        if let Some(before_id) = before_id {
+            if !bot.messages.contains_key(before_id) {
+                return None;
+            }
+
+            for id in iter.by_ref() {
+                if id == before_id {
+                    break;
+                }
+            }
+        }
+
+        let mut history: Vec<CachedMessage> = iter
+            .filter_map(|id| bot.messages.get(id))
+            .filter(|msg| before_time.is_none_or(|time| msg.time < time))
+            .filter(|msg| {
+                matches!(&msg.event, OneBotMessageEvent::Group { group_id: id, .. } if id == group_id)
+            })
+            .take(limit)
+            .cloned()
+            .collect();
+
+        history.reverse();
+        Some(history)
+    }
+
+    /// 从文件读取缓存的消息
+    pub fn load(&self, path: &Path) -> std::io::Result<()> {
+        let f = File::open(path)?;
+        let saved: Vec<(i64, Vec<CachedMessage>)> = serde_json::from_reader(BufReader::new(f))?;
+
+        for (bot_id, messages) in saved {
+            for msg in messages {
+                self.insert(bot_id, msg);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 将缓存的消息按时间顺序写入文件
+    ///
+    /// 先写入临时文件再替换, 写入中途退出不会损坏已有的文件
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        self.dirty.store(false, Ordering::Relaxed);
+        let saved: Vec<(i64, Vec<CachedMessage>)> = {
+            let bots = self.bots.lock().unwrap_or_else(|e| e.into_inner());
+            bots.iter()
+                .map(|(&bot_id, bot)| {
+                    let messages = bot
+                        .order
+                        .iter()
+                        .filter_map(|id| bot.messages.get(id))
+                        .cloned()
+                        .collect();
+                    (bot_id, messages)
+                })
+                .collect()
+        };
+
+        let tmp = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer(&mut writer, &saved)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&tmp, path)
+    }
+
+    /// 有新消息时写入文件
+    pub fn save_if_dirty(&self, path: &Path) -> std::io::Result<()> {
+        if self.dirty.load(Ordering::Relaxed) {
+            self.save(path)?;
+        }
+
+        Ok(())
+    }
+}
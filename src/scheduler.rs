@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::config::{SendQueueConfig, TargetQueueConfig};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SendTarget {
+    Group(i64),
+    Friend(i64),
+}
+
+/// 消息在发送队列中的排队情况
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueueReport {
+    /// 入队时前方等待的消息数
+    pub position: usize,
+    /// 在队列中等待的时间, 单位毫秒
+    pub waited: u64,
+}
+
+#[derive(Debug)]
+pub enum ScheduleError {
+    QueueFull { length: usize },
+    Timeout { position: usize },
+}
+
+struct TargetQueue {
+    pending: AtomicUsize,
+    last_send: tokio::sync::Mutex<Option<Instant>>,
+}
+
+struct PendingGuard<'a>(&'a AtomicUsize);
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// 清理空闲发送队列的间隔
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Queues {
+    queues: HashMap<(i64, SendTarget), Arc<TargetQueue>>,
+    last_prune: Instant,
+}
+
+/// 消息发送调度器, 为每个机器人的每个群与好友维护先进先出的发送队列
+pub struct SendScheduler {
+    config: SendQueueConfig,
+    prune_interval: Duration,
+    queues: Mutex<Queues>,
+}
+
+impl SendScheduler {
+    pub fn new(config: SendQueueConfig) -> Self {
+        Self::with_prune_interval(config, PRUNE_INTERVAL)
+    }
+
+    pub fn with_prune_interval(config: SendQueueConfig, prune_interval: Duration) -> Self {
+        Self {
+            config,
+            prune_interval,
+            queues: Mutex::new(Queues {
+                queues: HashMap::new(),
+                last_prune: Instant::now(),
+            }),
+        }
+    }
+
+    /// 在目标的发送队列中排队, 轮到时执行`send`
+    pub async fn schedule<F, Fu, T>(
+        &self,
+        bot_id: i64,
+        target: SendTarget,
+        send: F,
+    ) -> Result<(T, QueueReport), ScheduleError>
+    where
+        F: FnOnce() -> Fu,
+        Fu: Future<Output = T>,
+    {
+        if !self.config.enabled {
+            return Ok((send().await, QueueReport::default()));
+        }
+
+        let config = self.target_config(target);
+
+        // 持有锁时入队, 使清理时看到的空闲队列不会再被使用
+        let (queue, position) = {
+            let mut queues = self.queues.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            if now.duration_since(queues.last_prune) >= self.prune_interval {
+                self.prune(&mut queues.queues, now);
+                queues.last_prune = now;
+            }
+
+            let queue = Arc::clone(queues.queues.entry((bot_id, target)).or_insert_with(|| {
+                Arc::new(TargetQueue {
+                    pending: AtomicUsize::new(0),
+                    last_send: tokio::sync::Mutex::new(None),
+                })
+            }));
+            let position = queue.pending.fetch_add(1, Ordering::AcqRel);
+            (queue, position)
+        };
+
+        let _guard = PendingGuard(&queue.pending);
+        if config.max_length > 0 && position >= config.max_length {
+            return Err(ScheduleError::QueueFull {
+                length: config.max_length,
+            });
+        }
+
+        let start = Instant::now();
+        let wait = async {
+            let mut last_send = queue.last_send.lock().await;
+            if let Some(last) = *last_send {
+                let interval = next_interval(&config);
+                let elapsed = last.elapsed();
+                if elapsed < interval {
+                    tokio::time::sleep(interval - elapsed).await;
+                }
+            }
+            *last_send = Some(Instant::now());
+            last_send
+        };
+
+        let last_send = if config.timeout > 0 {
+            match tokio::time::timeout(Duration::from_millis(config.timeout), wait).await {
+                Ok(last_send) => last_send,
+                Err(_) => return Err(ScheduleError::Timeout { position }),
+            }
+        } else {
+            wait.await
+        };
+
+        let report = QueueReport {
+            position,
+            waited: start.elapsed().as_millis() as u64,
+        };
+
+        let result = send().await;
+        drop(last_send);
+
+        Ok((result, report))
+    }
+
+    /// 移除没有排队消息且已过最长发送间隔的队列, 这样的队列与新建的队列等价
+    fn prune(&self, queues: &mut HashMap<(i64, SendTarget), Arc<TargetQueue>>, now: Instant) {
+        queues.retain(|(_, target), queue| {
+            if queue.pending.load(Ordering::Acquire) > 0 {
+                return true;
+            }
+
+            let config = self.target_config(*target);
+            let max_interval = Duration::from_millis(config.min_interval + config.jitter);
+            match queue.last_send.try_lock() {
+                Ok(last_send) => {
+                    last_send.is_some_and(|last| now.duration_since(last) < max_interval)
+                }
+                Err(_) => true,
+            }
+        });
+    }
+
+    fn target_config(&self, target: SendTarget) -> TargetQueueConfig {
+        match target {
+            SendTarget::Group(_) => self.config.group,
+            SendTarget::Friend(_) => self.config.friend,
+        }
+    }
+
+    /// 当前的发送队列数量
+    #[cfg(test)]
+    pub fn queue_count(&self) -> usize {
+        let queues = self.queues.lock().unwrap_or_else(|e| e.into_inner());
+        queues.queues.len()
+    }
+}
+
+fn next_interval(config: &TargetQueueConfig) -> Duration {
+    let jitter = if config.jitter > 0 {
+        rand::thread_rng().gen_range(0..=config.jitter)
+    } else {
+        0
+    };
+
+    Duration::from_millis(config.min_interval + jitter)
+}
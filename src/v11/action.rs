@@ -0,0 +1,571 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::data::action::{Action, ActionData, ActionStatus, OneBotMessageAction};
+use crate::data::contact::{GroupInfo, GroupMemberInfo};
+use crate::data::message::{MessageElement, OneBotMessageEvent};
+use crate::error::ActionError;
+use crate::handler::{get_bot, handle, ActionContext};
+use crate::message_cache::CachedMessage;
+use crate::v11::event::{V11Sender, V11Status};
+use crate::v11::message::{from_elements, to_elements, V11Message, V11Segment};
+use crate::v11::MessageIds;
+
+#[derive(Debug, Deserialize)]
+pub struct V11Request {
+    pub action: String,
+    #[serde(default)]
+    pub params: Value,
+    #[serde(default)]
+    pub echo: Value,
+}
+
+/// 声明OneBot 11动作, 支持的动作列表由此生成, 均转换为OneBot 12动作处理
+macro_rules! v11_actions {
+    ($($name:literal => $variant:ident $fields:tt),* $(,)?) => {
+        #[derive(Debug, Deserialize)]
+        #[serde(tag = "action", content = "params")]
+        pub enum V11Action {
+            $(
+            #[serde(rename = $name)]
+            $variant $fields,
+            )*
+        }
+
+        /// 支持的OneBot 11动作
+        pub const SUPPORTED: &[&str] = &[$($name),*];
+    };
+}
+
+v11_actions! {
+    "send_private_msg" => SendPrivateMsg {
+        user_id: i64,
+        message: V11Message,
+        #[serde(default)]
+        auto_escape: bool,
+    },
+    "send_group_msg" => SendGroupMsg {
+        group_id: i64,
+        message: V11Message,
+        #[serde(default)]
+        auto_escape: bool,
+    },
+    "send_msg" => SendMsg {
+        message_type: Option<String>,
+        user_id: Option<i64>,
+        group_id: Option<i64>,
+        message: V11Message,
+        #[serde(default)]
+        auto_escape: bool,
+    },
+    "get_msg" => GetMsg {
+        message_id: i32,
+    },
+    "get_login_info" => GetLoginInfo {},
+    "get_stranger_info" => GetStrangerInfo {
+        user_id: i64,
+    },
+    "get_friend_list" => GetFriendList {},
+    "get_group_info" => GetGroupInfo {
+        group_id: i64,
+    },
+    "get_group_list" => GetGroupList {},
+    "get_group_member_info" => GetGroupMemberInfo {
+        group_id: i64,
+        user_id: i64,
+        #[serde(default)]
+        no_cache: bool,
+    },
+    "get_group_member_list" => GetGroupMemberList {
+        group_id: i64,
+    },
+    "set_group_name" => SetGroupName {
+        group_id: i64,
+        group_name: String,
+    },
+    "set_group_leave" => SetGroupLeave {
+        group_id: i64,
+        #[serde(default)]
+        is_dismiss: bool,
+    },
+    "set_group_card" => SetGroupCard {
+        group_id: i64,
+        user_id: i64,
+        #[serde(default)]
+        card: String,
+    },
+    "get_status" => GetStatus {},
+    "get_version_info" => GetVersionInfo {},
+    "can_send_image" => CanSendImage {},
+    "can_send_record" => CanSendRecord {},
+}
+
+#[derive(Debug, Serialize)]
+pub struct V11Response {
+    pub status: ActionStatus,
+    pub retcode: i64,
+    pub data: Option<V11Data>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub msg: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub wording: String,
+    #[serde(skip_serializing_if = "Value::is_null")]
+    pub echo: Value,
+}
+
+impl V11Response {
+    pub fn from_err(err: ActionError, echo: Value) -> Self {
+        // OneBot 11仅规定了请求错误的返回码, 其余错误沿用go-cqhttp的100
+        let retcode = match err {
+            ActionError::BadRequest(_)
+            | ActionError::BadParam(_)
+            | ActionError::UnsupportedParam(_)
+            | ActionError::UnsupportedSegment(_)
+            | ActionError::BadSegmentData(_) => 1400,
+            ActionError::UnsupportedAction(_) => 1404,
+            _ => 100,
+        };
+
+        Self {
+            status: ActionStatus::Failed,
+            retcode,
+            data: None,
+            msg: err.to_string(),
+            wording: err.to_string(),
+            echo,
+        }
+    }
+
+    pub fn from_data(data: Option<V11Data>, echo: Value) -> Self {
+        Self {
+            status: ActionStatus::Ok,
+            retcode: 0,
+            data,
+            msg: "".into(),
+            wording: "".into(),
+            echo,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum V11Data {
+    MessageId {
+        message_id: i32,
+    },
+    Message {
+        time: i64,
+        message_type: &'static str,
+        message_id: i32,
+        real_id: i32,
+        sender: V11Sender,
+        message: Vec<V11Segment>,
+    },
+    LoginInfo {
+        user_id: i64,
+        nickname: String,
+    },
+    StrangerInfo {
+        user_id: i64,
+        nickname: String,
+        sex: &'static str,
+        age: i32,
+    },
+    FriendList(Vec<V11Friend>),
+    GroupInfo(V11Group),
+    GroupList(Vec<V11Group>),
+    GroupMemberInfo(V11Member),
+    GroupMemberList(Vec<V11Member>),
+    Status(V11Status),
+    VersionInfo {
+        app_name: &'static str,
+        app_version: &'static str,
+        protocol_version: &'static str,
+    },
+    Yes {
+        yes: bool,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct V11Friend {
+    pub user_id: i64,
+    pub nickname: String,
+    pub remark: String,
+}
+
+/// Atri暂未提供群人数与群容量, 不返回`member_count`与`max_member_count`
+#[derive(Debug, Serialize)]
+pub struct V11Group {
+    pub group_id: i64,
+    pub group_name: String,
+}
+
+impl From<GroupInfo> for V11Group {
+    fn from(info: GroupInfo) -> Self {
+        Self {
+            group_id: info.group_id.parse().unwrap_or(0),
+            group_name: info.group_name,
+        }
+    }
+}
+
+/// Atri暂未提供群员身份, 入群时间与发言时间, 不返回`role`, `card_changeable`与各项时间,
+/// 以免机器人据此误判权限; 头衔等其余资料返回空值
+#[derive(Debug, Serialize)]
+pub struct V11Member {
+    pub group_id: i64,
+    pub user_id: i64,
+    pub nickname: String,
+    pub card: String,
+    pub sex: &'static str,
+    pub age: i32,
+    pub area: String,
+    pub level: String,
+    pub unfriendly: bool,
+    pub title: String,
+}
+
+impl V11Member {
+    fn new(group_id: i64, info: GroupMemberInfo) -> Self {
+        Self {
+            group_id,
+            user_id: info.user_id.parse().unwrap_or(0),
+            nickname: info.user_name,
+            card: info.user_displayname,
+            sex: "unknown",
+            age: 0,
+            area: "".into(),
+            level: "".into(),
+            unfriendly: false,
+            title: "".into(),
+        }
+    }
+}
+
+pub async fn handle_action(
+    V11Request {
+        action,
+        params,
+        echo,
+    }: V11Request,
+    ctx: &ActionContext,
+    ids: &MessageIds,
+) -> V11Response {
+    if !SUPPORTED.contains(&&*action) {
+        return V11Response::from_err(ActionError::UnsupportedAction(action), echo);
+    }
+
+    let params = match params {
+        Value::Null => Value::Object(Default::default()),
+        params => params,
+    };
+    let name = action.clone();
+    let action = serde_json::from_value::<V11Action>(serde_json::json!({
+        "action": action,
+        "params": params,
+    }));
+
+    let result = match action {
+        Ok(action) => match ctx.timeout.of(&name) {
+            Some(timeout) => tokio::time::timeout(timeout, handle_v11(action, ctx, ids))
+                .await
+                .unwrap_or(Err(ActionError::Timeout(name))),
+            None => handle_v11(action, ctx, ids).await,
+        },
+        Err(e) => Err(ActionError::BadParam(e.to_string())),
+    };
+
+    match result {
+        Ok(data) => V11Response::from_data(data, echo),
+        Err(e) => V11Response::from_err(e, echo),
+    }
+}
+
+async fn handle_v11(
+    action: V11Action,
+    ctx: &ActionContext,
+    ids: &MessageIds,
+) -> Result<Option<V11Data>, ActionError> {
+    let data = match action {
+        V11Action::SendPrivateMsg {
+            user_id,
+            message,
+            auto_escape,
+        } => {
+            let message = to_elements(message, auto_escape, ids)?;
+            send_message(ctx, ids, Some(user_id), None, message).await?
+        }
+        V11Action::SendGroupMsg {
+            group_id,
+            message,
+            auto_escape,
+        } => {
+            let message = to_elements(message, auto_escape, ids)?;
+            send_message(ctx, ids, None, Some(group_id), message).await?
+        }
+        V11Action::SendMsg {
+            message_type,
+            user_id,
+            group_id,
+            message,
+            auto_escape,
+        } => {
+            let message = to_elements(message, auto_escape, ids)?;
+            match message_type.as_deref() {
+                Some("private") => send_message(ctx, ids, user_id, None, message).await?,
+                Some("group") => send_message(ctx, ids, None, group_id, message).await?,
+                Some(other) => {
+                    return Err(ActionError::BadParam(format!("未知的消息类型: {}", other)));
+                }
+                None if group_id.is_some() => {
+                    send_message(ctx, ids, None, group_id, message).await?
+                }
+                None => send_message(ctx, ids, user_id, None, message).await?,
+            }
+        }
+        V11Action::GetMsg { message_id } => {
+            let id = ids.get(message_id).ok_or(ActionError::MessageNotFound)?;
+            let Some(ActionData::QQGetMessage(msg)) =
+                handle(Action::QQGetMessage { message_id: id }, None, ctx).await?
+            else {
+                return Err(unexpected_data());
+            };
+
+            let self_id = if msg.outgoing {
+                Some(get_bot(&None, ctx)?.id())
+            } else {
+                None
+            };
+            message_data(msg, message_id, self_id, ids)
+        }
+        V11Action::GetLoginInfo {} => {
+            let Some(ActionData::GetSelfInfo {
+                user_id, user_name, ..
+            }) = handle(Action::GetSelfInfo {}, None, ctx).await?
+            else {
+                return Err(unexpected_data());
+            };
+
+            V11Data::LoginInfo {
+                user_id: user_id.parse().unwrap_or(0),
+                nickname: user_name,
+            }
+        }
+        V11Action::GetStrangerInfo { user_id } => {
+            let action = Action::GetUserInfo {
+                user_id: user_id.to_string(),
+            };
+            let Some(ActionData::GetUserInfo(info)) = handle(action, None, ctx).await? else {
+                return Err(unexpected_data());
+            };
+
+            V11Data::StrangerInfo {
+                user_id,
+                nickname: info.user_name,
+                sex: "unknown",
+                age: 0,
+            }
+        }
+        V11Action::GetFriendList {} => {
+            let action = Action::GetFriendList { no_cache: false };
+            let Some(ActionData::GetFriendList(friends)) = handle(action, None, ctx).await? else {
+                return Err(unexpected_data());
+            };
+
+            V11Data::FriendList(
+                friends
+                    .into_iter()
+                    .map(|f| V11Friend {
+                        user_id: f.user_id.parse().unwrap_or(0),
+                        nickname: f.user_name,
+                        remark: f.user_remark,
+                    })
+                    .collect(),
+            )
+        }
+        V11Action::GetGroupInfo { group_id } => {
+            let action = Action::GetGroupInfo {
+                group_id: group_id.to_string(),
+            };
+            let Some(ActionData::GetGroupInfo(info)) = handle(action, None, ctx).await? else {
+                return Err(unexpected_data());
+            };
+
+            V11Data::GroupInfo(info.into())
+        }
+        V11Action::GetGroupList {} => {
+            let action = Action::GetGroupList { no_cache: false };
+            let Some(ActionData::GetGroupList(groups)) = handle(action, None, ctx).await? else {
+                return Err(unexpected_data());
+            };
+
+            V11Data::GroupList(groups.into_iter().map(V11Group::from).collect())
+        }
+        V11Action::GetGroupMemberInfo {
+            group_id,
+            user_id,
+            no_cache,
+        } => {
+            let action = Action::GetGroupMemberInfo {
+                group_id: group_id.to_string(),
+                user_id: user_id.to_string(),
+                no_cache,
+            };
+            let Some(ActionData::GetGroupMemberInfo(info)) = handle(action, None, ctx).await?
+            else {
+                return Err(unexpected_data());
+            };
+
+            V11Data::GroupMemberInfo(V11Member::new(group_id, info))
+        }
+        V11Action::GetGroupMemberList { group_id } => {
+            let action = Action::GetGroupMemberList {
+                group_id: group_id.to_string(),
+                no_cache: false,
+                offset: None,
+                limit: None,
+                name: None,
+            };
+            let Some(ActionData::GetGroupMemberList(members)) = handle(action, None, ctx).await?
+            else {
+                return Err(unexpected_data());
+            };
+
+            V11Data::GroupMemberList(
+                members
+                    .into_iter()
+                    .map(|info| V11Member::new(group_id, info))
+                    .collect(),
+            )
+        }
+        V11Action::SetGroupName {
+            group_id,
+            group_name,
+        } => {
+            let action = Action::SetGroupName {
+                group_id: group_id.to_string(),
+                group_name,
+            };
+            handle(action, None, ctx).await?;
+
+            return Ok(None);
+        }
+        V11Action::SetGroupLeave {
+            group_id,
+            is_dismiss,
+        } => {
+            // Atri暂未提供解散群
+            if is_dismiss {
+                return Err(ActionError::UnsupportedParam("暂不支持解散群".into()));
+            }
+
+            let action = Action::LeaveGroup {
+                group_id: group_id.to_string(),
+            };
+            handle(action, None, ctx).await?;
+
+            return Ok(None);
+        }
+        V11Action::SetGroupCard {
+            group_id,
+            user_id,
+            card,
+        } => {
+            let action = Action::QQSetGroupMemberCard {
+                group_id: group_id.to_string(),
+                user_id: user_id.to_string(),
+                card,
+            };
+            handle(action, None, ctx).await?;
+
+            return Ok(None);
+        }
+        V11Action::GetStatus {} => {
+            let Some(ActionData::GetStatus(status)) =
+                handle(Action::GetStatus {}, None, ctx).await?
+            else {
+                return Err(unexpected_data());
+            };
+
+            V11Data::Status(V11Status {
+                online: status.bots.iter().any(|bot| bot.online),
+                good: status.good,
+            })
+        }
+        V11Action::GetVersionInfo {} => V11Data::VersionInfo {
+            app_name: "atri_onebot",
+            app_version: env!("CARGO_PKG_VERSION"),
+            protocol_version: "v11",
+        },
+        // Atri暂未提供发送图片与语音的接口
+        V11Action::CanSendImage {} | V11Action::CanSendRecord {} => V11Data::Yes { yes: false },
+    };
+
+    Ok(Some(data))
+}
+
+async fn send_message(
+    ctx: &ActionContext,
+    ids: &MessageIds,
+    user_id: Option<i64>,
+    group_id: Option<i64>,
+    message: Vec<MessageElement>,
+) -> Result<V11Data, ActionError> {
+    let action = match (user_id, group_id) {
+        (_, Some(group_id)) => OneBotMessageAction::Group {
+            message,
+            group_id: group_id.to_string(),
+        },
+        (Some(user_id), None) => OneBotMessageAction::Private {
+            message,
+            user_id: user_id.to_string(),
+            group_id: None,
+        },
+        (None, None) => {
+            return Err(ActionError::BadParam("缺少消息接收者".into()));
+        }
+    };
+
+    let Some(ActionData::SendMessage { message_id, .. }) =
+        handle(Action::SendMessage(action), None, ctx).await?
+    else {
+        return Err(unexpected_data());
+    };
+
+    Ok(V11Data::MessageId {
+        message_id: ids.numeric(&message_id),
+    })
+}
+
+/// 转换缓存的消息, 机器人发出的消息以`self_id`为发送者
+fn message_data(
+    msg: CachedMessage,
+    message_id: i32,
+    self_id: Option<i64>,
+    ids: &MessageIds,
+) -> V11Data {
+    let (message_type, user_id, message) = match msg.event {
+        OneBotMessageEvent::Private { message, user_id } => ("private", user_id, message),
+        OneBotMessageEvent::Group {
+            message, user_id, ..
+        } => ("group", user_id, message),
+        OneBotMessageEvent::Channel { message, .. } => ("guild", "".into(), message),
+    };
+
+    V11Data::Message {
+        time: msg.time as i64,
+        message_type,
+        message_id,
+        real_id: message_id,
+        sender: V11Sender {
+            user_id: self_id.unwrap_or_else(|| user_id.parse().unwrap_or(0)),
+        },
+        message: from_elements(&message.message, Some(ids)),
+    }
+}
+
+fn unexpected_data() -> ActionError {
+    ActionError::InternalHandlerError("OneBot 12动作返回了意外的数据".into())
+}
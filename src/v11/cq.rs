@@ -0,0 +1,121 @@
+//! CQ码字符串与消息段之间的转换
+//!
+//! 文本中的`&`, `[`, `]`分别转义为`&amp;`, `&#91;`, `&#93;`,
+//! CQ码参数中的`,`还需转义为`&#44;`
+
+use serde_json::{Map, Value};
+
+use crate::data::message::MessageElement;
+use crate::error::ActionError;
+use crate::v11::message::{self, V11Segment};
+
+pub fn escape(s: &str, param: bool) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '[' => escaped.push_str("&#91;"),
+            ']' => escaped.push_str("&#93;"),
+            ',' if param => escaped.push_str("&#44;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+pub fn unescape(s: &str) -> String {
+    s.replace("&#91;", "[")
+        .replace("&#93;", "]")
+        .replace("&#44;", ",")
+        .replace("&amp;", "&")
+}
+
+/// 解析CQ码字符串
+pub fn parse(s: &str) -> Result<Vec<V11Segment>, ActionError> {
+    let mut segments = vec![];
+    let mut rest = s;
+
+    while let Some(start) = rest.find("[CQ:") {
+        push_text(&mut segments, &rest[..start]);
+
+        let code = &rest[start + 4..];
+        let end = code
+            .find(']')
+            .ok_or_else(|| ActionError::BadSegmentData(format!("未闭合的CQ码: {}", code)))?;
+        segments.push(parse_code(&code[..end])?);
+
+        rest = &code[end + 1..];
+    }
+    push_text(&mut segments, rest);
+
+    Ok(segments)
+}
+
+fn push_text(segments: &mut Vec<V11Segment>, text: &str) {
+    if !text.is_empty() {
+        segments.push(V11Segment::Text {
+            text: unescape(text),
+        });
+    }
+}
+
+fn parse_code(code: &str) -> Result<V11Segment, ActionError> {
+    let mut parts = code.split(',');
+    let kind = parts.next().unwrap_or_default();
+    if !V11Segment::KINDS.contains(&kind) {
+        return Err(ActionError::UnsupportedSegment(kind.into()));
+    }
+
+    let mut data = Map::new();
+    for param in parts {
+        let (key, value) = param
+            .split_once('=')
+            .ok_or_else(|| ActionError::BadSegmentData(format!("无效的CQ码参数: {}", param)))?;
+        data.insert(key.into(), Value::String(unescape(value)));
+    }
+
+    let mut segment = Map::new();
+    segment.insert("type".into(), Value::String(kind.into()));
+    segment.insert("data".into(), Value::Object(data));
+
+    serde_json::from_value(Value::Object(segment))
+        .map_err(|e| ActionError::BadSegmentData(format!("{}: {}", kind, e)))
+}
+
+/// 将消息段渲染为CQ码字符串
+pub fn render(segments: &[V11Segment]) -> String {
+    let mut s = String::new();
+    for segment in segments {
+        if let V11Segment::Text { text } = segment {
+            s.push_str(&escape(text, false));
+            continue;
+        }
+
+        let value = serde_json::to_value(segment).unwrap_or_default();
+        s.push_str("[CQ:");
+        s.push_str(value["type"].as_str().unwrap_or_default());
+        if let Some(data) = value["data"].as_object() {
+            for (key, value) in data {
+                s.push(',');
+                s.push_str(key);
+                s.push('=');
+                s.push_str(&escape(value.as_str().unwrap_or_default(), true));
+            }
+        }
+        s.push(']');
+    }
+
+    s
+}
+
+pub fn to_elements(s: &str) -> Result<Vec<MessageElement>, ActionError> {
+    parse(s)?
+        .into_iter()
+        .map(|segment| message::to_element(segment, None))
+        .collect()
+}
+
+pub fn from_elements(elems: &[MessageElement]) -> String {
+    render(&message::from_elements(elems, None))
+}
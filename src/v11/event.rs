@@ -0,0 +1,168 @@
+use atri_plugin::bot::Bot;
+use serde::Serialize;
+
+use crate::config::MessageFormat;
+use crate::data::event::{OneBotEvent, OneBotMetaEvent, OneBotTypedEvent};
+use crate::data::message::{MessageElement, OneBotMessageEvent};
+use crate::v11::message::{from_elements, V11Message};
+use crate::v11::{cq, MessageIds};
+
+#[derive(Debug, Serialize)]
+pub struct V11Event {
+    pub time: i64,
+    pub self_id: i64,
+    #[serde(flatten)]
+    pub typed: V11TypedEvent,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "post_type")]
+#[serde(rename_all = "snake_case")]
+pub enum V11TypedEvent {
+    Message(V11MessageEvent),
+    MetaEvent(V11MetaEvent),
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "message_type")]
+#[serde(rename_all = "snake_case")]
+pub enum V11MessageEvent {
+    Private {
+        sub_type: &'static str,
+        message_id: i32,
+        user_id: i64,
+        message: V11Message,
+        raw_message: String,
+        font: i32,
+        sender: V11Sender,
+    },
+    Group {
+        sub_type: &'static str,
+        message_id: i32,
+        group_id: i64,
+        user_id: i64,
+        /// Atri暂未提供匿名信息
+        anonymous: Option<()>,
+        message: V11Message,
+        raw_message: String,
+        font: i32,
+        sender: V11Sender,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "meta_event_type")]
+#[serde(rename_all = "snake_case")]
+pub enum V11MetaEvent {
+    Lifecycle { sub_type: &'static str },
+    Heartbeat { status: V11Status, interval: i64 },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct V11Sender {
+    pub user_id: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct V11Status {
+    pub online: bool,
+    pub good: bool,
+}
+
+impl V11Event {
+    /// 转换为OneBot 11事件, 没有对应事件时返回`None`
+    pub fn from_event(
+        event: &OneBotEvent,
+        format: MessageFormat,
+        ids: &MessageIds,
+    ) -> Option<Self> {
+        let self_id = event
+            .bot_self
+            .as_ref()
+            .and_then(|bot| bot.user_id.parse().ok())
+            .unwrap_or(0);
+
+        let typed = match &event.typed {
+            OneBotTypedEvent::Meta(OneBotMetaEvent::Heartbeat { interval }) => {
+                V11TypedEvent::MetaEvent(V11MetaEvent::Heartbeat {
+                    status: V11Status {
+                        online: !Bot::list().is_empty(),
+                        good: true,
+                    },
+                    interval: *interval,
+                })
+            }
+            OneBotTypedEvent::Message(OneBotMessageEvent::Private { message, user_id }) => {
+                let user_id = user_id.parse().unwrap_or(0);
+                let (content, raw_message) = encode_message(&message.message, format, ids);
+
+                V11TypedEvent::Message(V11MessageEvent::Private {
+                    sub_type: "friend",
+                    message_id: ids.numeric(&message.message_id),
+                    user_id,
+                    message: content,
+                    raw_message,
+                    font: 0,
+                    sender: V11Sender { user_id },
+                })
+            }
+            OneBotTypedEvent::Message(OneBotMessageEvent::Group {
+                message,
+                group_id,
+                user_id,
+            }) => {
+                let user_id = user_id.parse().unwrap_or(0);
+                let (content, raw_message) = encode_message(&message.message, format, ids);
+
+                V11TypedEvent::Message(V11MessageEvent::Group {
+                    sub_type: "normal",
+                    message_id: ids.numeric(&message.message_id),
+                    group_id: group_id.parse().unwrap_or(0),
+                    user_id,
+                    anonymous: None,
+                    message: content,
+                    raw_message,
+                    font: 0,
+                    sender: V11Sender { user_id },
+                })
+            }
+            OneBotTypedEvent::Meta(OneBotMetaEvent::StatusUpdate { .. })
+            | OneBotTypedEvent::Notice
+            | OneBotTypedEvent::Message(OneBotMessageEvent::Channel { .. }) => return None,
+        };
+
+        Some(Self {
+            time: event.time as i64,
+            self_id,
+            typed,
+        })
+    }
+
+    /// 连接建立时发送的生命周期事件
+    pub fn connect(time: f64) -> Self {
+        Self {
+            time: time as i64,
+            self_id: 0,
+            typed: V11TypedEvent::MetaEvent(V11MetaEvent::Lifecycle {
+                sub_type: "connect",
+            }),
+        }
+    }
+}
+
+/// 按上报格式转换消息, 同时返回CQ码形式的原始消息
+fn encode_message(
+    elems: &[MessageElement],
+    format: MessageFormat,
+    ids: &MessageIds,
+) -> (V11Message, String) {
+    let segments = from_elements(elems, Some(ids));
+    let raw_message = cq::render(&segments);
+
+    let message = match format {
+        MessageFormat::Array => V11Message::Segments(segments),
+        MessageFormat::String => V11Message::Text(raw_message.clone()),
+    };
+
+    (message, raw_message)
+}
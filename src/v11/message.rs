@@ -0,0 +1,179 @@
+use serde::{Deserialize, Serialize};
+
+use crate::data::message::MessageElement;
+use crate::error::ActionError;
+use crate::v11::{cq, MessageIds};
+
+/// 声明OneBot 11消息段及其类型名
+macro_rules! v11_segments {
+    ($($kind:literal => $variant:ident $fields:tt),* $(,)?) => {
+        /// OneBot 11消息段, 参数均按CQ码的习惯使用字符串
+        #[derive(Debug, Clone, Serialize, Deserialize)]
+        #[serde(tag = "type", content = "data")]
+        pub enum V11Segment {
+            $(
+            #[serde(rename = $kind)]
+            $variant $fields,
+            )*
+        }
+
+        impl V11Segment {
+            /// 支持的消息段类型
+            pub const KINDS: &'static [&'static str] = &[$($kind),*];
+        }
+    };
+}
+
+v11_segments! {
+    "text" => Text {
+        text: String,
+    },
+    "image" => Image {
+        file: String,
+    },
+    "record" => Record {
+        file: String,
+    },
+    "video" => Video {
+        file: String,
+    },
+    "at" => At {
+        qq: String,
+    },
+    "location" => Location {
+        lat: String,
+        lon: String,
+        #[serde(default)]
+        title: String,
+        #[serde(default)]
+        content: String,
+    },
+    "reply" => Reply {
+        id: String,
+    },
+}
+
+/// 消息段数组, 单个消息段或CQ码字符串
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum V11Message {
+    Segments(Vec<V11Segment>),
+    Segment(V11Segment),
+    Text(String),
+}
+
+/// 转换动作请求中的消息, `auto_escape`为`true`时字符串按纯文本处理
+pub fn to_elements(
+    message: V11Message,
+    auto_escape: bool,
+    ids: &MessageIds,
+) -> Result<Vec<MessageElement>, ActionError> {
+    let segments = match message {
+        V11Message::Segments(segments) => segments,
+        V11Message::Segment(segment) => vec![segment],
+        V11Message::Text(text) if auto_escape => vec![V11Segment::Text { text }],
+        V11Message::Text(text) => cq::parse(&text)?,
+    };
+
+    segments
+        .into_iter()
+        .map(|segment| to_element(segment, Some(ids)))
+        .collect()
+}
+
+/// 转换消息段, 未指定`ids`时回复的消息ID按原样使用
+pub fn to_element(
+    segment: V11Segment,
+    ids: Option<&MessageIds>,
+) -> Result<MessageElement, ActionError> {
+    let elem = match segment {
+        V11Segment::Text { text } => MessageElement::Text { text },
+        V11Segment::Image { file } => MessageElement::Image { file_id: file },
+        V11Segment::Record { file } => MessageElement::Voice { file_id: file },
+        V11Segment::Video { file } => MessageElement::Video { file_id: file },
+        V11Segment::At { qq } if qq == "all" => MessageElement::MentionAll {},
+        V11Segment::At { qq } => MessageElement::Mention { user_id: qq },
+        V11Segment::Location {
+            lat,
+            lon,
+            title,
+            content,
+        } => MessageElement::Location {
+            latitude: parse_coord(&lat)?,
+            longitude: parse_coord(&lon)?,
+            title,
+            content,
+        },
+        V11Segment::Reply { id } => {
+            let message_id =
+                match ids {
+                    Some(ids) => id.parse().ok().and_then(|id| ids.get(id)).ok_or_else(|| {
+                        ActionError::BadSegmentData(format!("未知的消息ID: {}", id))
+                    })?,
+                    None => id,
+                };
+
+            MessageElement::Reply {
+                message_id,
+                user_id: "".into(),
+            }
+        }
+    };
+
+    Ok(elem)
+}
+
+fn parse_coord(coord: &str) -> Result<f64, ActionError> {
+    coord
+        .parse()
+        .map_err(|e| ActionError::BadSegmentData(format!("{}: {}", coord, e)))
+}
+
+/// 转换为OneBot 11消息段, 没有对应消息段的元素将被忽略
+///
+/// 未指定`ids`时回复的消息ID按原样使用
+pub fn from_elements(elems: &[MessageElement], ids: Option<&MessageIds>) -> Vec<V11Segment> {
+    elems
+        .iter()
+        .filter_map(|elem| {
+            let segment = match elem {
+                MessageElement::Text { text } => V11Segment::Text { text: text.clone() },
+                MessageElement::Image { file_id } => V11Segment::Image {
+                    file: file_id.clone(),
+                },
+                MessageElement::Mention { user_id } => V11Segment::At {
+                    qq: user_id.clone(),
+                },
+                MessageElement::MentionAll {} => V11Segment::At { qq: "all".into() },
+                MessageElement::Voice { file_id } | MessageElement::Audio { file_id } => {
+                    V11Segment::Record {
+                        file: file_id.clone(),
+                    }
+                }
+                MessageElement::Video { file_id } => V11Segment::Video {
+                    file: file_id.clone(),
+                },
+                MessageElement::Location {
+                    latitude,
+                    longitude,
+                    title,
+                    content,
+                } => V11Segment::Location {
+                    lat: latitude.to_string(),
+                    lon: longitude.to_string(),
+                    title: title.clone(),
+                    content: content.clone(),
+                },
+                MessageElement::Reply { message_id, .. } => V11Segment::Reply {
+                    id: match ids {
+                        Some(ids) => ids.numeric(message_id).to_string(),
+                        None => message_id.clone(),
+                    },
+                },
+                MessageElement::File { .. } | MessageElement::QQUnknown {} => return None,
+            };
+
+            Some(segment)
+        })
+        .collect()
+}
@@ -0,0 +1,63 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+pub mod action;
+pub mod cq;
+pub mod event;
+pub mod message;
+
+#[derive(Default)]
+struct Ids {
+    next: i32,
+    numeric: HashMap<String, i32>,
+    ids: HashMap<i32, String>,
+    order: VecDeque<i32>,
+}
+
+/// OneBot 11使用数字消息ID, 在此与OneBot 12的字符串消息ID相互映射
+///
+/// 超出容量时淘汰最早分配的ID
+pub struct MessageIds {
+    capacity: usize,
+    inner: Mutex<Ids>,
+}
+
+impl MessageIds {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            inner: Mutex::new(Ids::default()),
+        }
+    }
+
+    /// 获取消息的数字ID, 不存在时分配新的ID
+    pub fn numeric(&self, message_id: &str) -> i32 {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(&id) = inner.numeric.get(message_id) {
+            return id;
+        }
+
+        inner.next = inner.next.checked_add(1).unwrap_or(1);
+        let id = inner.next;
+        if let Some(old) = inner.ids.insert(id, message_id.to_owned()) {
+            inner.numeric.remove(&old);
+        }
+        inner.numeric.insert(message_id.to_owned(), id);
+        inner.order.push_back(id);
+
+        while inner.order.len() > self.capacity {
+            if let Some(id) = inner.order.pop_front() {
+                if let Some(old) = inner.ids.remove(&id) {
+                    inner.numeric.remove(&old);
+                }
+            }
+        }
+
+        id
+    }
+
+    pub fn get(&self, id: i32) -> Option<String> {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.ids.get(&id).cloned()
+    }
+}
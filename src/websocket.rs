@@ -1,18 +1,29 @@
-use crate::config::HeartbeatConfig;
-use crate::data::action::{ActionRequest, ActionResponse};
+use crate::config::{HeartbeatConfig, MessageFormat, OneBotProtocol, WebSocketConfig};
+use crate::contact_cache::ContactCache;
+use crate::data::action::{Action, ActionRequest, ActionResponse};
 use crate::data::event::{BotStatus, OneBotEvent, OneBotMetaEvent, OneBotStatus, OneBotTypedEvent};
-use crate::data::message::OneBotMessageEvent;
-use crate::handler::handle_action;
+use crate::data::message::{Chat, OneBotMessage, OneBotMessageEvent};
+use crate::error::ActionError;
+use crate::handler::{handle_cancellable, parse_request, ActionContext};
+use crate::message_cache::{CachedMessage, MessageCache};
+use crate::v11;
+use crate::v11::action::{V11Request, V11Response};
+use crate::v11::cq;
+use crate::v11::event::V11Event;
+use crate::v11::MessageIds;
 use actix_web::{web, HttpRequest, HttpResponse};
 use actix_ws::Message;
 use atri_plugin::bot::Bot;
+use atri_plugin::contact::member::Member;
 use atri_plugin::event::Event;
 use atri_plugin::listener::{Listener, ListenerGuard};
 use atri_plugin::{error, info};
+use serde_json::Value;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, SystemTime};
+use tokio::sync::{Semaphore, TryAcquireError};
 
 pub async fn start_websocket(
     req: HttpRequest,
@@ -31,6 +42,32 @@ pub async fn start_websocket(
         return HttpResponse::ExpectationFailed().await;
     };
 
+    let ws_config = req
+        .app_data::<WebSocketConfig>()
+        .copied()
+        .unwrap_or_default();
+
+    let ctx = if let Some(ctx) = req.app_data::<Arc<ActionContext>>() {
+        ActionContext {
+            in_flight: Some(Arc::default()),
+            ..ctx.with_query(req.query_string())
+        }
+    } else {
+        return HttpResponse::ExpectationFailed().await;
+    };
+
+    let format = ctx.message_format;
+    let protocol = req
+        .app_data::<OneBotProtocol>()
+        .copied()
+        .unwrap_or_default();
+
+    let ids = if let Some(ids) = req.app_data::<Arc<MessageIds>>() {
+        Arc::clone(ids)
+    } else {
+        return HttpResponse::ExpectationFailed().await;
+    };
+
     let remote = req
         .connection_info()
         .realip_remote_addr()
@@ -41,7 +78,14 @@ pub async fn start_websocket(
     info!("WebSocket已连接, Remote address: {:?}", remote);
     let mut heartbeat_session = session.clone();
 
+    if protocol == OneBotProtocol::V11 {
+        let connect =
+            serde_json::to_string(&V11Event::connect(sys_time())).expect("无法序列化OneBot 11事件");
+        let _ = session.text(connect).await;
+    }
+
     if heartbeat.enabled {
+        let ids = Arc::clone(&ids);
         tokio::task::spawn_local(async move {
             let interval = heartbeat.interval;
             assert!(interval > 0);
@@ -55,7 +99,7 @@ pub async fn start_websocket(
             };
 
             while let Ok(()) = heartbeat_session
-                .text(serde_json::to_string(&heartbeat_pkt).unwrap_or_default())
+                .text(encode_event(&heartbeat_pkt, protocol, format, &ids).unwrap_or_default())
                 .await
             {
                 let uuid = uuid::Uuid::new_v4();
@@ -67,13 +111,14 @@ pub async fn start_websocket(
     }
 
     let mut event_handler = session.clone();
+    let event_ids = Arc::clone(&ids);
     tokio::task::spawn_local(async move {
         while let Ok(event) = rx.recv().await {
-            let str = serde_json::to_string(&*event);
-            match str {
+            match encode_event(&event, protocol, format, &event_ids) {
+                Ok(str) if str.is_empty() => {}
                 Ok(str) => {
                     let result = event_handler.text(str).await;
-                    if let Err(_) = result {
+                    if result.is_err() {
                         return;
                     }
                 }
@@ -84,19 +129,45 @@ pub async fn start_websocket(
         }
     });
 
+    let max_in_flight = ws_config.max_in_flight.max(1);
+    let in_flight = Arc::new(Semaphore::new(max_in_flight));
     tokio::task::spawn_local(async move {
         while let Some(Ok(msg)) = stream.recv().await {
             match msg {
                 Message::Text(json) => {
-                    let rsp = match serde_json::from_str::<ActionRequest>(&json) {
-                        Ok(req) => handle_action(req).await,
-                        Err(e) => ActionResponse::from_err(e, 10001, None),
+                    let req = match protocol {
+                        OneBotProtocol::V12 => {
+                            Request::V12(parse_request(&json, ctx.message_format))
+                        }
+                        OneBotProtocol::V11 => Request::V11(serde_json::from_str(&json)),
                     };
 
-                    let str = serde_json::to_string(&rsp).expect("无法序列化OneBot动作响应");
-                    if session.text(str).await.is_err() {
-                        break;
-                    }
+                    // 达到同时处理的动作数上限时直接返回繁忙错误, 读取不会因此阻塞;
+                    // 取消动作不占用名额, 以便在动作阻塞时仍能取消
+                    let permit = if req.is_cancel() {
+                        None
+                    } else {
+                        match Arc::clone(&in_flight).try_acquire_owned() {
+                            Ok(permit) => Some(permit),
+                            Err(TryAcquireError::NoPermits) => {
+                                let err = ActionError::TooManyInFlight(max_in_flight);
+                                if session.text(req.reject(err)).await.is_err() {
+                                    break;
+                                }
+                                continue;
+                            }
+                            Err(TryAcquireError::Closed) => break,
+                        }
+                    };
+
+                    let ctx = ctx.clone();
+                    let ids = Arc::clone(&ids);
+                    let mut session = session.clone();
+                    tokio::task::spawn_local(async move {
+                        let str = req.handle(&ctx, &ids).await;
+                        let _ = session.text(str).await;
+                        drop(permit);
+                    });
                 }
                 Message::Binary(_) => {}
                 Message::Continuation(_) => {}
@@ -118,7 +189,79 @@ pub async fn start_websocket(
     Ok(resp)
 }
 
-pub fn listener(tx: tokio::sync::broadcast::Sender<Arc<OneBotEvent>>) -> ListenerGuard {
+/// 已解析的动作请求
+enum Request {
+    V12(Result<ActionRequest, (ActionError, Option<String>)>),
+    V11(serde_json::Result<V11Request>),
+}
+
+impl Request {
+    fn is_cancel(&self) -> bool {
+        matches!(
+            self,
+            Self::V12(Ok(ActionRequest {
+                action: Action::QQCancel { .. },
+                ..
+            }))
+        )
+    }
+
+    /// 不执行动作, 以`err`作为响应, 请求本身无效时仍返回解析错误
+    fn reject(self, err: ActionError) -> String {
+        match self {
+            Self::V12(req) => {
+                let rsp = match req {
+                    Ok(req) => ActionResponse::from_err(err, req.echo),
+                    Err((e, echo)) => ActionResponse::from_err(e, echo),
+                };
+                serde_json::to_string(&rsp)
+            }
+            Self::V11(req) => {
+                let rsp = match req {
+                    Ok(req) => V11Response::from_err(err, req.echo),
+                    Err(e) => {
+                        V11Response::from_err(ActionError::BadRequest(e.to_string()), Value::Null)
+                    }
+                };
+                serde_json::to_string(&rsp)
+            }
+        }
+        .expect("无法序列化OneBot动作响应")
+    }
+
+    /// 执行动作并序列化响应
+    async fn handle(self, ctx: &ActionContext, ids: &MessageIds) -> String {
+        match self {
+            Self::V12(req) => {
+                let rsp = match req {
+                    Ok(req) => handle_cancellable(req, ctx).await,
+                    Err((e, echo)) => ActionResponse::from_err(e, echo),
+                };
+                serde_json::to_string(&rsp)
+            }
+            // OneBot 11没有取消动作, 请求不经`handle_cancellable`记录
+            Self::V11(req) => {
+                let rsp = match req {
+                    Ok(req) => v11::action::handle_action(req, ctx, ids).await,
+                    Err(e) => {
+                        V11Response::from_err(ActionError::BadRequest(e.to_string()), Value::Null)
+                    }
+                };
+                serde_json::to_string(&rsp)
+            }
+        }
+        .expect("无法序列化OneBot动作响应")
+    }
+}
+
+/// 匿名消息的发送者账号
+const ANONYMOUS_ID: i64 = 80000000;
+
+pub fn listener(
+    tx: tokio::sync::broadcast::Sender<Arc<OneBotEvent>>,
+    messages: Arc<MessageCache>,
+    contacts: Arc<ContactCache>,
+) -> ListenerGuard {
     let counter = Arc::new(AtomicBool::new(false));
 
     let cnt = counter.clone();
@@ -150,22 +293,46 @@ pub fn listener(tx: tokio::sync::broadcast::Sender<Arc<OneBotEvent>>) -> Listene
     Listener::listening_on_always(move |e: Event| {
         let tx = tx.clone();
         let cnt = counter.clone();
+        let messages = Arc::clone(&messages);
+        let contacts = Arc::clone(&contacts);
         async move {
             match e {
                 Event::BotLogin(_) => {
                     cnt.swap(true, Ordering::Relaxed);
+                    contacts.on_bot_login(Bot::list().iter().map(Bot::id));
                 }
                 Event::GroupMessage(e) => {
                     let msg = e.message();
+                    let time = msg.metadata().time as f64;
+                    let sender = match e.sender() {
+                        Member::Named(sender) => sender.id(),
+                        Member::Anonymous(_) => ANONYMOUS_ID,
+                    };
+                    let event = OneBotMessageEvent::Group {
+                        message: OneBotMessage::from_chain(msg, Chat::Group(e.group().id())),
+                        group_id: e.group().id().to_string(),
+                        user_id: sender.to_string(),
+                    };
+
+                    let bot = e.bot();
+                    if sender != ANONYMOUS_ID {
+                        contacts.on_group_message(bot.id(), e.group().id(), sender);
+                    }
+                    messages.insert(
+                        bot.id(),
+                        CachedMessage {
+                            time,
+                            outgoing: false,
+                            event: event.clone(),
+                        },
+                    );
+
                     let ob = OneBotEvent {
                         id: uuid::Uuid::new_v4().to_string(),
-                        time: msg.metadata().time as f64,
-                        typed: OneBotTypedEvent::Message(OneBotMessageEvent::Group {
-                            message: msg.into(),
-                            group_id: e.group().id().to_string(),
-                        }),
+                        time,
+                        typed: OneBotTypedEvent::Message(event),
                         sub_type: "",
-                        bot_self: Some(e.bot().into()),
+                        bot_self: Some(bot.into()),
                     };
 
                     let arc = Arc::new(ob);
@@ -174,27 +341,65 @@ pub fn listener(tx: tokio::sync::broadcast::Sender<Arc<OneBotEvent>>) -> Listene
                 }
                 Event::FriendMessage(e) => {
                     let msg = e.message();
+                    let time = msg.metadata().time as f64;
+                    let event = OneBotMessageEvent::Private {
+                        message: OneBotMessage::from_chain(msg, Chat::Private(e.friend().id())),
+                        user_id: e.friend().id().to_string(),
+                    };
+
+                    let bot = e.bot();
+                    contacts.on_friend_message(bot.id(), e.friend().id());
+                    messages.insert(
+                        bot.id(),
+                        CachedMessage {
+                            time,
+                            outgoing: false,
+                            event: event.clone(),
+                        },
+                    );
+
                     let ob = OneBotEvent {
                         id: uuid::Uuid::new_v4().to_string(),
-                        time: msg.metadata().time as f64,
-                        typed: OneBotTypedEvent::Message(OneBotMessageEvent::Private {
-                            message: msg.into(),
-                            user_id: e.friend().id().to_string(),
-                        }),
+                        time,
+                        typed: OneBotTypedEvent::Message(event),
                         sub_type: "",
-                        bot_self: Some(e.bot().into()),
+                        bot_self: Some(bot.into()),
                     };
 
                     let arc = Arc::new(ob);
 
                     let _ = tx.send(arc);
                 }
+                // Atri暂未提供临时会话消息事件, 无法上报`qq.temp`私聊消息
                 _ => {}
             }
         }
     })
 }
 
-fn sys_time() -> f64 {
+/// 按协议版本与消息格式序列化事件, 没有对应事件时返回空字符串
+fn encode_event(
+    event: &OneBotEvent,
+    protocol: OneBotProtocol,
+    format: MessageFormat,
+    ids: &MessageIds,
+) -> serde_json::Result<String> {
+    match protocol {
+        OneBotProtocol::V12 => match (&event.typed, format) {
+            (OneBotTypedEvent::Message(msg), MessageFormat::String) => {
+                let mut value = serde_json::to_value(event)?;
+                value["message"] = cq::from_elements(&msg.message().message).into();
+                serde_json::to_string(&value)
+            }
+            _ => serde_json::to_string(event),
+        },
+        OneBotProtocol::V11 => match V11Event::from_event(event, format, ids) {
+            Some(event) => serde_json::to_string(&event),
+            None => Ok(String::new()),
+        },
+    }
+}
+
+pub fn sys_time() -> f64 {
     SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs_f64()
 }